@@ -0,0 +1,298 @@
+extern crate proc_macro;
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Data, DeriveInput, Error, Fields, Ident, Pat, PatOr, Token};
+
+/// Patterns for the header bytes of an instruction, as given in `#[apdu(...)]` attributes.
+#[derive(Default)]
+struct HeaderPatterns {
+    cla: Option<Pat>,
+    ins: Option<Pat>,
+    p1: Option<Pat>,
+    p2: Option<Pat>,
+}
+
+/// A single `name = pattern` argument of an `#[apdu(...)]` attribute.
+struct Arg {
+    name: Ident,
+    pat: Pat,
+}
+
+impl Parse for Arg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        input.parse::<Token![=]>()?;
+        // Top-level alternatives are not parsed by `Pat::parse`
+        let first: Pat = input.parse()?;
+        if !input.peek(Token![|]) {
+            return Ok(Arg { name, pat: first });
+        }
+        let mut cases = Punctuated::new();
+        cases.push_value(first);
+        while input.peek(Token![|]) {
+            cases.push_punct(input.parse()?);
+            cases.push_value(input.parse()?);
+        }
+        let pat = Pat::Or(PatOr {
+            attrs: Vec::new(),
+            leading_vert: None,
+            cases,
+        });
+        Ok(Arg { name, pat })
+    }
+}
+
+impl HeaderPatterns {
+    /// Collects the patterns from the `#[apdu(...)]` attributes in `attrs`.
+    fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut patterns = HeaderPatterns::default();
+        for attr in attrs.iter().filter(|a| a.path.is_ident("apdu")) {
+            let args = attr.parse_args_with(Punctuated::<Arg, Token![,]>::parse_terminated)?;
+            for arg in args {
+                let slot = match arg.name.to_string().as_str() {
+                    "cla" => &mut patterns.cla,
+                    "ins" => &mut patterns.ins,
+                    "p1" => &mut patterns.p1,
+                    "p2" => &mut patterns.p2,
+                    _ => {
+                        return Err(Error::new_spanned(
+                            &arg.name,
+                            "expected one of `cla`, `ins`, `p1`, `p2`",
+                        ))
+                    }
+                };
+                if slot.is_some() {
+                    return Err(Error::new_spanned(&arg.name, "duplicate argument"));
+                }
+                *slot = Some(arg.pat);
+            }
+        }
+        Ok(patterns)
+    }
+}
+
+/// Returns an expression checking whether `value` matches `pat`, or `true` if there is no pattern.
+fn matches(value: TokenStream2, pat: &Option<Pat>) -> TokenStream2 {
+    match pat {
+        Some(pat) => quote! { ::core::matches!(#value, #pat) },
+        None => quote! { true },
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "ApduInstruction can only be derived for enumerations",
+            ))
+        }
+    };
+    if data.variants.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "ApduInstruction cannot be derived for an empty enumeration",
+        ));
+    }
+    let defaults = HeaderPatterns::from_attrs(&input.attrs)?;
+    if defaults.ins.is_some() || defaults.p1.is_some() || defaults.p2.is_some() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "only `cla` can be set for the whole enumeration",
+        ));
+    }
+
+    let mut checks = Vec::new();
+    let mut seen = Vec::new();
+    for variant in data.variants.iter() {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(
+                variant,
+                "ApduInstruction variants cannot have fields",
+            ));
+        }
+        let patterns = HeaderPatterns::from_attrs(&variant.attrs)?;
+        if patterns.ins.is_none() {
+            return Err(Error::new_spanned(
+                variant,
+                "missing `#[apdu(ins = ...)]` attribute",
+            ));
+        }
+        let cla = patterns.cla.or_else(|| defaults.cla.clone());
+        // Variants with the same patterns as a previous one could never be returned
+        let key = [&cla, &patterns.ins, &patterns.p1, &patterns.p2]
+            .map(|pat| pat.as_ref().map(|pat| quote! { #pat }.to_string()));
+        if seen.contains(&key) {
+            return Err(Error::new_spanned(
+                variant,
+                "duplicate instruction, same patterns as a previous variant",
+            ));
+        }
+        seen.push(key);
+        let cla_ok = matches(quote! { header.cla }, &cla);
+        let ins_ok = matches(quote! { header.ins }, &patterns.ins);
+        let p1_ok = matches(quote! { header.p1 }, &patterns.p1);
+        let p2_ok = matches(quote! { header.p2 }, &patterns.p2);
+        let ident = &variant.ident;
+        checks.push(quote! {
+            if #ins_ok {
+                ins_found = true;
+                if #cla_ok {
+                    cla_found = true;
+                    if #p1_ok && #p2_ok {
+                        return Ok(#name::#ident);
+                    }
+                }
+            }
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::core::convert::TryFrom<::ledger_device_sdk::io::ApduHeader>
+            for #name #ty_generics #where_clause
+        {
+            type Error = ::ledger_device_sdk::io::StatusWords;
+
+            fn try_from(
+                header: ::ledger_device_sdk::io::ApduHeader,
+            ) -> ::core::result::Result<Self, Self::Error> {
+                let mut ins_found = false;
+                let mut cla_found = false;
+                #(#checks)*
+                Err(if cla_found {
+                    ::ledger_device_sdk::io::StatusWords::BadP1P2
+                } else if ins_found {
+                    ::ledger_device_sdk::io::StatusWords::BadCla
+                } else {
+                    ::ledger_device_sdk::io::StatusWords::BadIns
+                })
+            }
+        }
+    })
+}
+
+/// Implements `TryFrom<ApduHeader>` for an enumeration of instructions, with per-variant
+/// `#[apdu(cla = ..., ins = ..., p1 = ..., p2 = ...)]` patterns.
+///
+/// See the crate README for details.
+#[proc_macro_derive(ApduInstruction, attributes(apdu))]
+pub fn derive_apdu_instruction(item: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(item as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn expand_err(input: DeriveInput) -> String {
+        match expand(input) {
+            Ok(_) => panic!("expansion should fail"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn expand_instructions() {
+        let tokens = expand(parse_quote! {
+            #[apdu(cla = 0xe0)]
+            enum Instruction {
+                #[apdu(ins = 0x03, p1 = 0, p2 = 0)]
+                GetVersion,
+                #[apdu(ins = 0x06, p1 = 0x00 | 0x80)]
+                SignTx,
+                #[apdu(cla = 0xb0, ins = 0x06)]
+                Other,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(tokens.contains(":: core :: matches ! (header . cla , 0xe0)"));
+        assert!(tokens.contains(":: core :: matches ! (header . p1 , 0x00 | 0x80)"));
+        assert!(tokens.contains(":: core :: matches ! (header . cla , 0xb0)"));
+    }
+
+    #[test]
+    fn unit_variants_only() {
+        let err = expand_err(parse_quote! {
+            enum Instruction {
+                #[apdu(ins = 0x01)]
+                Sign(u8),
+            }
+        });
+        assert_eq!(err, "ApduInstruction variants cannot have fields");
+        let err = expand_err(parse_quote! {
+            struct Instruction;
+        });
+        assert_eq!(err, "ApduInstruction can only be derived for enumerations");
+    }
+
+    #[test]
+    fn duplicate_ins() {
+        let err = expand_err(parse_quote! {
+            #[apdu(cla = 0xe0)]
+            enum Instruction {
+                #[apdu(ins = 0x01, p1 = 0)]
+                First,
+                #[apdu(cla = 0xe0, ins = 0x01, p1 = 0)]
+                Second,
+            }
+        });
+        assert_eq!(
+            err,
+            "duplicate instruction, same patterns as a previous variant"
+        );
+        // Same INS with different parameters is allowed
+        expand(parse_quote! {
+            enum Instruction {
+                #[apdu(ins = 0x01, p1 = 0)]
+                First,
+                #[apdu(ins = 0x01, p1 = 1)]
+                Second,
+            }
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let err = expand_err(parse_quote! {
+            #[apdu(ins = 0x01)]
+            enum Instruction {
+                #[apdu(ins = 0x01)]
+                First,
+            }
+        });
+        assert_eq!(err, "only `cla` can be set for the whole enumeration");
+        let err = expand_err(parse_quote! {
+            enum Instruction {
+                #[apdu(cla = 0xe0)]
+                First,
+            }
+        });
+        assert_eq!(err, "missing `#[apdu(ins = ...)]` attribute");
+        let err = expand_err(parse_quote! {
+            enum Instruction {
+                #[apdu(ins = 0x01, ins = 0x02)]
+                First,
+            }
+        });
+        assert_eq!(err, "duplicate argument");
+        let err = expand_err(parse_quote! {
+            enum Instruction {
+                #[apdu(ins = 0x01, le = 0x02)]
+                First,
+            }
+        });
+        assert_eq!(err, "expected one of `cla`, `ins`, `p1`, `p2`");
+    }
+}
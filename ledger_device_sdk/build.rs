@@ -2,6 +2,11 @@ use std::path::PathBuf;
 use std::{env, error::Error};
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // Host builds need neither device selection nor linker scripts
+    if env::var_os("CARGO_FEATURE_MOCK").is_some() {
+        return Ok(());
+    }
+
     enum Device {
         NanoS,
         NanoSPlus,
@@ -1,3 +1,4 @@
+use crate::io::ConnectionEvent;
 use ledger_secure_sdk_sys::{LEDGER_BLE_receive, LEDGER_BLE_send, LEDGER_BLE_set_recv_buffer};
 
 pub fn receive(apdu_buffer: &mut [u8], spi_buffer: &[u8]) {
@@ -12,3 +13,92 @@ pub fn send(buffer: &[u8]) {
         LEDGER_BLE_send(buffer.as_ptr(), buffer.len() as u16);
     }
 }
+
+// BLE SEPH packets carry an HCI packet, forwarded as is to `LEDGER_BLE_receive`:
+// [ tag, len (u16 BE), packet type, ... ]. Events are laid out as defined by the Bluetooth Core
+// Specification (Vol 4, Part E, 5.4.4): [ packet type, event code, params len, params... ].
+
+/// HCI packet type of events
+const HCI_EVENT_PKT_TYPE: u8 = 0x04;
+/// HCI Disconnection Complete event code (Vol 4, Part E, 7.7.5)
+const HCI_DISCONNECTION_COMPLETE_EVT_CODE: u8 = 0x05;
+/// HCI LE Meta event code, and codes of its LE (Enhanced) Connection Complete subevents
+/// (Vol 4, Part E, 7.7.65.1 and 7.7.65.10)
+const HCI_LE_META_EVT_CODE: u8 = 0x3e;
+const HCI_LE_CONNECTION_COMPLETE_SUBEVT_CODE: u8 = 0x01;
+const HCI_LE_ENHANCED_CONNECTION_COMPLETE_SUBEVT_CODE: u8 = 0x0a;
+
+/// Returns the connection state change notified by a received BLE SEPH packet, if any.
+pub fn connection_event(spi_buffer: &[u8]) -> Option<ConnectionEvent> {
+    let len = u16::from_be_bytes([*spi_buffer.get(1)?, *spi_buffer.get(2)?]) as usize;
+    let packet = spi_buffer.get(3..3 + len)?;
+    let (&[packet_type, event_code, params_len], params) = packet.split_first_chunk()?;
+    if packet_type != HCI_EVENT_PKT_TYPE {
+        return None;
+    }
+    match (event_code, params.get(..params_len as usize)?) {
+        // params = [ status, handle (u16), reason ]
+        (HCI_DISCONNECTION_COMPLETE_EVT_CODE, [0, ..]) => Some(ConnectionEvent::BleDisconnected),
+        // params = [ subevent code, status, ... ]
+        (
+            HCI_LE_META_EVT_CODE,
+            [HCI_LE_CONNECTION_COMPLETE_SUBEVT_CODE
+            | HCI_LE_ENHANCED_CONNECTION_COMPLETE_SUBEVT_CODE, 0, ..],
+        ) => Some(ConnectionEvent::BleConnected),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn ble_connection_events() {
+        // SEPH BLE packets in a 128-byte SPI buffer
+        let packet = |data: &[u8]| {
+            let mut buffer = [0u8; 128];
+            buffer[0] = 0x16;
+            buffer[1..3].copy_from_slice(&(data.len() as u16).to_be_bytes());
+            buffer[3..3 + data.len()].copy_from_slice(data);
+            buffer
+        };
+
+        let disconnected = packet(&[0x04, 0x05, 0x04, 0x00, 0x01, 0x00, 0x13]);
+        assert_eq!(
+            connection_event(&disconnected),
+            Some(ConnectionEvent::BleDisconnected)
+        );
+        let connected = packet(&[0x04, 0x3e, 0x03, 0x01, 0x00, 0x01]);
+        assert_eq!(
+            connection_event(&connected),
+            Some(ConnectionEvent::BleConnected)
+        );
+        let enhanced = packet(&[0x04, 0x3e, 0x02, 0x0a, 0x00]);
+        assert_eq!(
+            connection_event(&enhanced),
+            Some(ConnectionEvent::BleConnected)
+        );
+
+        // Failed connection, other events, other packets
+        assert_eq!(
+            connection_event(&packet(&[0x04, 0x3e, 0x02, 0x01, 0x3e])),
+            None
+        );
+        assert_eq!(connection_event(&packet(&[0x04, 0x0e, 0x01, 0x00])), None);
+        assert_eq!(connection_event(&packet(&[0x02, 0x05, 0x01, 0x00])), None);
+
+        // Parameters beyond the SEPH or HCI lengths are ignored
+        let mut truncated = packet(&[0x04, 0x05]);
+        truncated[5] = 0x04;
+        assert_eq!(connection_event(&truncated), None);
+        assert_eq!(
+            connection_event(&packet(&[0x04, 0x3e, 0x01, 0x01, 0x00])),
+            None
+        );
+        assert_eq!(connection_event(&[0x16, 0x00]), None);
+    }
+}
@@ -1,9 +1,22 @@
+use crate::io::{Reply, SyscallError};
+
+/// Size of the CCID buffer of the C SDK, which does not depend on the size of the APDU buffer.
+const CCID_BUFFER_SIZE: usize = 260;
+
 extern "C" {
-    pub static mut G_io_apdu_buffer: [u8; 260];
+    pub static mut G_io_apdu_buffer: [u8; CCID_BUFFER_SIZE];
     pub fn io_usb_ccid_reply_bare(length: u16);
 }
 
+/// Transmits the response `buf`. Responses which do not fit in the CCID buffer are replaced by
+/// the [`SyscallError::Overflow`] status word.
 pub fn send(buf: &[u8]) {
+    let overflow = Reply::from(SyscallError::Overflow).0.to_be_bytes();
+    let buf = if buf.len() <= CCID_BUFFER_SIZE {
+        buf
+    } else {
+        &overflow[..]
+    };
     unsafe {
         G_io_apdu_buffer[..buf.len()].copy_from_slice(buf);
         io_usb_ccid_reply_bare(buf.len() as u16);
@@ -0,0 +1,253 @@
+//! Single-threaded cooperative executor, to write application flows as `async` functions.
+//!
+//! An [`Executor`] owns the [`Comm`] while it runs a future: it receives events one at a time,
+//! and polls the future after each of them. The futures returned by [`Executor::next_event`],
+//! [`Executor::next_command`], [`Executor::next_button`] and [`Executor::sleep`] complete when
+//! a matching event is received, and futures waiting for several events at once can be combined
+//! with [`select`].
+//!
+//! BOLOS APDUs (such as GetAppInfo) are answered by the [`Comm`] as usual while the flow is
+//! waiting, and commands which no future is waiting for are rejected with
+//! [`StatusWords::UnexpectedApdu`].
+//!
+//! # Examples
+//!
+//! ```
+//! let mut comm = Comm::new();
+//! let exec = Executor::<Instruction>::new(&mut comm);
+//! exec.block_on(async {
+//!     loop {
+//!         match exec.next_command().await {
+//!             Instruction::SignTx => {
+//!                 let tx = parse_tx(&mut exec.comm());
+//!                 // Keep answering GetVersion while the user reviews the transaction
+//!                 let approved = match select(review(&exec, &tx), serve_version(&exec)).await {
+//!                     Either::First(approved) => approved,
+//!                     Either::Second(never) => match never {},
+//!                 };
+//!                 ...
+//!             }
+//!             ...
+//!         }
+//!     }
+//! });
+//! ```
+use crate::io::{
+    ApduHeader, Comm, DefaultTransport, Event, Reply, StatusWords, Transport, APDU_BUFFER_SIZE,
+};
+use core::cell::{Cell, RefCell, RefMut};
+use core::future::{poll_fn, Future};
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll, Waker};
+
+#[cfg(not(any(target_os = "stax", target_os = "flex")))]
+use crate::buttons::ButtonEvent;
+
+/// Executor running a future, with the events received by a [`Comm`].
+///
+/// Futures are polled again after every event, and do not need to be woken: the [`Waker`] given
+/// to them does nothing.
+pub struct Executor<'c, T, Tr = DefaultTransport, const N: usize = APDU_BUFFER_SIZE> {
+    comm: RefCell<&'c mut Comm<Tr, N>>,
+    /// Last event received, until a future takes it
+    event: RefCell<Option<Event<T>>>,
+    /// Number of ticker events received
+    ticks: Cell<u32>,
+}
+
+impl<'c, T, Tr: Transport, const N: usize> Executor<'c, T, Tr, N>
+where
+    T: TryFrom<ApduHeader>,
+    Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+{
+    pub fn new(comm: &'c mut Comm<Tr, N>) -> Self {
+        Executor {
+            comm: RefCell::new(comm),
+            event: RefCell::new(None),
+            ticks: Cell::new(0),
+        }
+    }
+
+    /// Borrows the [`Comm`], for instance to read a command or transmit a response.
+    ///
+    /// The returned reference must not be held across an `.await`: the executor panics if it is
+    /// still borrowed when it waits for the next event.
+    pub fn comm(&self) -> RefMut<'_, Comm<Tr, N>> {
+        RefMut::map(self.comm.borrow_mut(), |comm| &mut **comm)
+    }
+
+    /// Returns the number of ticker events received since the executor has been created.
+    pub fn ticks(&self) -> u32 {
+        self.ticks.get()
+    }
+
+    /// Runs `future` to completion, and returns its output.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+
+            // Events which have not been taken are dropped, and commands rejected
+            if let Some(Event::Command(_)) = self.event.take() {
+                self.comm().reply(StatusWords::UnexpectedApdu);
+            }
+
+            let event = self.comm().next_event::<T>();
+            if let Event::Ticker = event {
+                self.ticks.set(self.ticks.get().wrapping_add(1));
+            }
+            self.event.replace(Some(event));
+        }
+    }
+
+    /// Waits for the next event.
+    pub fn next_event(&self) -> impl Future<Output = Event<T>> + use<'_, 'c, T, Tr, N> {
+        poll_fn(|_| match self.event.take() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        })
+    }
+
+    /// Waits for the next command. Other events are left to other futures.
+    pub fn next_command(&self) -> impl Future<Output = T> + use<'_, 'c, T, Tr, N> {
+        self.take(|event| match event {
+            Event::Command(command) => Ok(command),
+            event => Err(event),
+        })
+    }
+
+    /// Waits for the next button event. Other events are left to other futures.
+    #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+    pub fn next_button(&self) -> impl Future<Output = ButtonEvent> + use<'_, 'c, T, Tr, N> {
+        self.take(|event| match event {
+            Event::Button(button) => Ok(button),
+            event => Err(event),
+        })
+    }
+
+    /// Waits for `ticks` ticker events, counted from the call to this method.
+    ///
+    /// Ticker events are not taken, so several futures can wait at the same time.
+    pub fn sleep(&self, ticks: u32) -> impl Future<Output = ()> + use<'_, 'c, T, Tr, N> {
+        let start = self.ticks.get();
+        poll_fn(move |_| {
+            if self.ticks.get().wrapping_sub(start) >= ticks {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Waits for an event accepted by `filter`, which gives back the events it does not accept.
+    fn take<R>(
+        &self,
+        filter: fn(Event<T>) -> Result<R, Event<T>>,
+    ) -> impl Future<Output = R> + use<'_, 'c, T, Tr, N, R> {
+        poll_fn(move |_| {
+            let mut slot = self.event.borrow_mut();
+            match slot.take().map(filter) {
+                Some(Ok(value)) => Poll::Ready(value),
+                Some(Err(event)) => {
+                    *slot = Some(event);
+                    Poll::Pending
+                }
+                None => Poll::Pending,
+            }
+        })
+    }
+}
+
+/// Output of [`select`]: the output of the future which has completed first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Future returned by [`select`].
+pub struct Select<A, B> {
+    first: A,
+    second: B,
+}
+
+/// Waits for the first of two futures to complete, and drops the other one.
+///
+/// The futures are polled in order: if both can complete on the same event, `first` wins and
+/// the event is not given to `second`.
+pub fn select<A: Future, B: Future>(first: A, second: B) -> Select<A, B> {
+    Select { first, second }
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: the futures are pinned with `self`, and never moved out of it
+        let this = unsafe { self.get_unchecked_mut() };
+        let first = unsafe { Pin::new_unchecked(&mut this.first) };
+        if let Poll::Ready(output) = first.poll(cx) {
+            return Poll::Ready(Either::First(output));
+        }
+        let second = unsafe { Pin::new_unchecked(&mut this.second) };
+        second.poll(cx).map(Either::Second)
+    }
+}
+
+/// Polls `future` once, and returns its output if it has completed.
+///
+/// This is used to run `async` functions which do not wait for events, outside of an
+/// [`Executor`].
+pub fn now_or_never<F: Future>(future: F) -> Option<F::Output> {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(future).poll(&mut cx) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn executor_events() {
+        let mut comm = Comm::new();
+        comm.transport.push_command(&[0xe0, 0x01, 0x00, 0x00]);
+        comm.transport.push_ticker();
+        comm.transport.push_command(&[0xe0, 0x02, 0x00, 0x00]);
+        comm.transport.push_ticker();
+        comm.transport.push_button(ButtonEvent::BothButtonsRelease);
+        comm.transport.push_ticker();
+        comm.transport.push_ticker();
+
+        let exec = Executor::<ApduHeader>::new(&mut comm);
+        let (ins, button) = exec.block_on(async {
+            let header = exec.next_command().await;
+            exec.comm().reply_ok();
+            // The second command is rejected while waiting for a button
+            let button = select(exec.next_button(), exec.sleep(5)).await;
+            exec.sleep(2).await;
+            (header.ins, button)
+        });
+        assert_eq!(ins, 0x01);
+        assert_eq!(button, Either::First(ButtonEvent::BothButtonsRelease));
+        assert_eq!(exec.ticks(), 4);
+
+        let comm = exec.comm.into_inner();
+        let response = comm.transport.pop_response();
+        assert_eq!(response.as_deref(), Some(&[0x90, 0x00][..]));
+        let sw = (StatusWords::UnexpectedApdu as u16).to_be_bytes();
+        assert_eq!(comm.transport.pop_response().as_deref(), Some(&sw[..]));
+        assert_eq!(comm.transport.pending_events(), 0);
+
+        assert_eq!(now_or_never(async { 1 }), Some(1));
+        assert_eq!(now_or_never(core::future::pending::<()>()), None);
+    }
+}
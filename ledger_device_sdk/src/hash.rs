@@ -1,3 +1,4 @@
+use crate::io::{Reply, SyscallError};
 use ledger_secure_sdk_sys::{
     cx_hash_final, cx_hash_get_size, cx_hash_no_throw, cx_hash_t, cx_hash_update,
     CX_INVALID_PARAMETER, CX_LAST, CX_OK,
@@ -30,6 +31,16 @@ impl From<HashError> for u32 {
     }
 }
 
+impl From<HashError> for Reply {
+    fn from(e: HashError) -> Reply {
+        match e {
+            HashError::InvalidParameter => SyscallError::InvalidParameter.into(),
+            HashError::InvalidOutputLength => SyscallError::Overflow.into(),
+            HashError::InternalError => SyscallError::Unspecified.into(),
+        }
+    }
+}
+
 pub trait HashInit: Sized {
     fn as_ctx_mut(&mut self) -> &mut cx_hash_t;
     fn as_ctx(&self) -> &cx_hash_t;
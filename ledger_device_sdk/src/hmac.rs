@@ -1,4 +1,5 @@
 //! Hash-based message authentication code (HMAC) related functions
+use crate::io::{Reply, SyscallError};
 use ledger_secure_sdk_sys::{
     cx_hmac_final, cx_hmac_no_throw, cx_hmac_t, cx_hmac_update, CX_INVALID_PARAMETER, CX_LAST,
     CX_OK,
@@ -29,6 +30,16 @@ impl From<HMACError> for u32 {
     }
 }
 
+impl From<HMACError> for Reply {
+    fn from(e: HMACError) -> Reply {
+        match e {
+            HMACError::InvalidParameter => SyscallError::InvalidParameter.into(),
+            HMACError::InvalidOutputLength => SyscallError::Overflow.into(),
+            HMACError::InternalError => SyscallError::Unspecified.into(),
+        }
+    }
+}
+
 /// Defines the behavior of a rust HMAC object.
 /// The implementation for a given algorithm is done using a rust macro
 /// to avoid code duplication since only the C structures and functions
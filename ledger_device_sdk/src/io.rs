@@ -1,17 +1,50 @@
-#[cfg(any(target_os = "nanox", target_os = "stax", target_os = "flex"))]
-use crate::ble;
 #[cfg(not(any(target_os = "stax", target_os = "flex")))]
-use ledger_secure_sdk_sys::buttons::{get_button_event, ButtonEvent, ButtonsState};
-use ledger_secure_sdk_sys::seph as sys_seph;
+use ledger_secure_sdk_sys::buttons::ButtonEvent;
+#[cfg(not(feature = "mock"))]
 use ledger_secure_sdk_sys::*;
 
-#[cfg(feature = "ccid")]
-use crate::ccid;
-use crate::seph;
 use core::convert::{Infallible, TryFrom};
 use core::ops::{Index, IndexMut};
 
-#[derive(Copy, Clone)]
+pub mod apdu;
+pub mod deadline;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod reader;
+#[cfg(not(feature = "mock"))]
+pub mod secure_channel;
+#[cfg(not(feature = "mock"))]
+pub mod session;
+pub mod status;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod transport;
+pub mod writer;
+
+pub use apdu::*;
+pub use apdu_derive::ApduInstruction;
+pub use deadline::*;
+#[cfg(feature = "mock")]
+pub use mock::*;
+pub use reader::*;
+#[cfg(not(feature = "mock"))]
+pub use secure_channel::*;
+#[cfg(not(feature = "mock"))]
+pub use session::*;
+pub use status::*;
+#[cfg(feature = "trace")]
+pub use trace::*;
+pub use transport::*;
+pub use writer::*;
+
+/// Transport used by [`Comm::new`]: [`SephTransport`] on devices, or `MockTransport` when
+/// building for the host with the `mock` feature.
+#[cfg(not(feature = "mock"))]
+pub type DefaultTransport = SephTransport;
+#[cfg(feature = "mock")]
+pub type DefaultTransport = MockTransport;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u16)]
 pub enum StatusWords {
     Ok = 0x9000,
@@ -21,11 +54,35 @@ pub enum StatusWords {
     BadP1P2 = 0x6e02,
     BadLen = 0x6e03,
     UserCancelled = 0x6e04,
+    UnexpectedApdu = 0x6e05,
     Unknown = 0x6d00,
     Panic = 0xe000,
     DeviceLocked = 0x5515,
+    LastCommandExpected = 0x6883,
 }
 
+/// Default size of the APDU buffer of [`Comm`]: a short APDU (5-byte header and 255 bytes of
+/// data), or a short response (256 bytes of data) and its status word.
+pub const APDU_BUFFER_SIZE: usize = 260;
+
+/// Minimum size of the APDU buffer of [`Comm`]: a command header, or the mandatory fields of the
+/// BOLOS GetAppInfo response, with a status word, and the encryption of the response by the
+/// secure channel.
+#[cfg(not(feature = "mock"))]
+pub const MIN_APDU_BUFFER_SIZE: usize = 5 + 2 + SecureChannel::OVERHEAD;
+#[cfg(feature = "mock")]
+pub const MIN_APDU_BUFFER_SIZE: usize = 5 + 2;
+
+/// CLA bit signalling that the command is part of an ISO 7816-4 chain and
+/// that more commands will follow.
+const CLA_CHAINING: u8 = 0x10;
+
+/// INS byte of the ISO 7816-4 GET RESPONSE command.
+const INS_GET_RESPONSE: u8 = 0xc0;
+
+/// Maximum length of a response part transmitted with response chaining.
+const RESPONSE_CHUNK_MAX: usize = 256;
+
 #[derive(Debug)]
 #[repr(u8)]
 pub enum SyscallError {
@@ -60,7 +117,9 @@ impl From<u32> for SyscallError {
 
 /// Provide a type that will be used for replying
 /// an APDU with either a StatusWord or an SyscallError
-#[derive(Debug)]
+///
+/// Application-specific status words can be declared with [`status_words!`](crate::status_words).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct Reply(pub u16);
 
@@ -86,6 +145,10 @@ impl From<Infallible> for Reply {
 }
 
 /// Possible events returned by [`Comm::next_event`]
+///
+/// [`Event::Connection`] is only returned once enabled with [`Comm::set_connection_events`],
+/// but exhaustive matches on `Event` written before its addition need a new arm, such as
+/// `Event::Connection(_) => {}`.
 #[derive(Eq, PartialEq)]
 pub enum Event<T> {
     /// APDU event
@@ -97,22 +160,233 @@ pub enum Event<T> {
     TouchEvent,
     /// Ticker
     Ticker,
+    /// Connection or power state change, only returned if enabled with
+    /// [`Comm::set_connection_events`]
+    Connection(ConnectionEvent),
+}
+
+/// Connection and power state changes of the transports.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// USB bus reset, signalling the connection of a host
+    UsbReset,
+    /// USB bus suspended, when the host is sleeping or the cable is unplugged
+    UsbSuspend,
+    /// USB bus resumed after a suspension
+    UsbResume,
+    /// BLE connection established
+    BleConnected,
+    /// BLE connection terminated
+    BleDisconnected,
 }
 
+/// Processing of the commands received while the device is locked, set with
+/// [`Comm::set_lock_policy`].
+///
+/// BOLOS commands (CLA `0xb0`) are always processed. Whether the device is locked is reported
+/// by the [`Transport`] (see [`Transport::is_device_locked`]).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Commands are processed as if the device was unlocked.
+    #[default]
+    Ignore,
+    /// Commands are rejected with [`StatusWords::DeviceLocked`].
+    Reject,
+    /// The user is asked to validate the PIN, then the command is processed, or rejected with
+    /// [`StatusWords::DeviceLocked`] if the device is still locked.
+    ValidatePin,
+}
+
+/// Converts an event reported by a [`Transport`], which cannot be a command, to an event of
+/// any command type.
+fn cast_event<T>(event: Event<Infallible>) -> Event<T> {
+    match event {
+        Event::Command(never) => match never {},
+        #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+        Event::Button(button) => Event::Button(button),
+        #[cfg(any(target_os = "stax", target_os = "flex"))]
+        Event::TouchEvent => Event::TouchEvent,
+        Event::Ticker => Event::Ticker,
+        Event::Connection(event) => Event::Connection(event),
+    }
+}
+
+/// Handler of application-specific BOLOS APDUs (CLA `0xb0`), registered with
+/// [`Comm::set_bolos_handler`].
+///
+/// The handler is given the header of the APDU, and returns [`None`] if it does not handle it.
+/// Otherwise, the response data can be appended to the [`Comm`], and the response is transmitted
+/// with the returned result (see [`Comm::reply_result`]).
+pub type BolosHandler<Tr = DefaultTransport, const N: usize = APDU_BUFFER_SIZE> =
+    fn(&mut Comm<Tr, N>, ApduHeader) -> Option<Result<(), Reply>>;
+
 /// Manages the communication of the device: receives events such as button presses, incoming
 /// APDU requests, and provides methods to build and transmit APDU responses.
-pub struct Comm {
-    pub apdu_buffer: [u8; 260],
+///
+/// Events are received and responses transmitted through a [`Transport`], which is by default
+/// [`DefaultTransport`].
+///
+/// The APDU buffer holds `N` bytes, [`APDU_BUFFER_SIZE`] by default: a larger buffer allows
+/// extended APDUs on devices with more RAM, and a smaller one saves RAM on Nano S. It must hold
+/// at least [`MIN_APDU_BUFFER_SIZE`] bytes, which is checked at compile time.
+///
+/// # Examples
+///
+/// ```
+/// let mut comm = Comm::<_, 512>::with_buffer_size(DefaultTransport::new());
+/// ```
+pub struct Comm<Tr = DefaultTransport, const N: usize = APDU_BUFFER_SIZE> {
+    pub apdu_buffer: [u8; N],
     pub rx: usize,
     pub tx: usize,
     pub event_pending: bool,
-    #[cfg(not(any(target_os = "stax", target_os = "flex")))]
-    buttons: ButtonsState,
+    /// Transport used to receive events and transmit responses.
+    pub transport: Tr,
     /// Expected value for the APDU CLA byte.
     /// If defined, [`Comm`] will automatically reply with [`StatusWords::BadCla`] when an APDU
     /// with wrong CLA byte is received. If set to [`None`], all CLA are accepted.
     /// Can be set using [`Comm::set_expected_cla`] method.
     pub expected_cla: Option<u8>,
+    /// Reassembly state for ISO 7816-4 command chaining.
+    /// Disabled by default, can be enabled using [`Comm::set_chaining_buffer`] method.
+    chain: Option<CommandChain>,
+    /// Storage for responses larger than the APDU buffer, transmitted by parts with GET RESPONSE.
+    /// Disabled by default, can be enabled using [`Comm::set_response_buffer`] method.
+    response: Option<ResponseChain>,
+    /// Set when appended data did not fit in the response, which is then replaced by
+    /// [`SyscallError::Overflow`].
+    overflow: bool,
+    /// Parse APDUs with the rules of former SDK versions instead of ISO 7816-4.
+    /// Can be set using [`Comm::set_legacy_apdu_parsing`] method.
+    legacy_apdu_parsing: bool,
+    /// Application handler for BOLOS APDUs, called before the built-in ones.
+    /// Can be set using [`Comm::set_bolos_handler`] method.
+    bolos_handler: Option<BolosHandler<Tr, N>>,
+    /// Whether the built-in BOLOS quit command (INS `0xa7`) is supported.
+    /// Enabled by default, can be changed using [`Comm::set_bolos_quit`] method.
+    bolos_quit: bool,
+    /// Whether [`Event::Connection`] events are returned.
+    /// Disabled by default, can be enabled using [`Comm::set_connection_events`] method.
+    connection_events: bool,
+    /// Encrypted channel through which commands and responses may be exchanged.
+    /// Disabled by default, can be enabled using [`Comm::set_secure_channel`] method.
+    #[cfg(not(feature = "mock"))]
+    secure_channel: Option<SecureChannel>,
+    /// Processing of the commands received while the device is locked.
+    /// Can be set using [`Comm::set_lock_policy`] method.
+    lock_policy: LockPolicy,
+    /// Last APDUs exchanged, recorded with the `trace` feature.
+    #[cfg(feature = "trace")]
+    trace: Trace,
+}
+
+/// Command chaining state: data of chained commands is accumulated in a
+/// caller-provided buffer until the last command of the chain is received.
+struct CommandChain {
+    buffer: &'static mut [u8],
+    /// Number of data bytes accumulated so far.
+    len: usize,
+    /// Header of the chain in progress (with the chaining bit cleared), if any.
+    header: Option<ApduHeader>,
+    /// Set when the last command of the chain has been received, until the
+    /// response is transmitted.
+    complete: bool,
+}
+
+impl CommandChain {
+    fn reset(&mut self) {
+        self.len = 0;
+        self.header = None;
+        self.complete = false;
+    }
+
+    /// Processes an incoming APDU.
+    ///
+    /// Returns `Ok(true)` if a command is ready to be handled by the application (either an
+    /// unchained command, or the last command of a chain), or `Ok(false)` if more commands of the
+    /// chain are expected.
+    fn push(&mut self, apdu: &[u8], legacy: bool) -> Result<bool, StatusWords> {
+        let chained = apdu[0] & CLA_CHAINING != 0;
+        let header = ApduHeader {
+            cla: apdu[0] & !CLA_CHAINING,
+            ins: apdu[1],
+            p1: apdu[2],
+            p2: apdu[3],
+        };
+
+        match self.header {
+            // Unchained command outside of a chain
+            None if !chained => return Ok(true),
+            None => self.header = Some(header),
+            Some(h) => {
+                if h.cla != header.cla
+                    || h.ins != header.ins
+                    || h.p1 != header.p1
+                    || h.p2 != header.p2
+                {
+                    self.reset();
+                    return Err(StatusWords::LastCommandExpected);
+                }
+            }
+        }
+
+        let data = parse_apdu(apdu, legacy)?.data;
+        if self.len + data.len() > self.buffer.len() {
+            self.reset();
+            return Err(StatusWords::BadLen);
+        }
+        self.buffer[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+
+        self.complete = !chained;
+        Ok(self.complete)
+    }
+}
+
+/// Response chaining state: a response which does not fit in the APDU buffer is stored in a
+/// caller-provided buffer, and transmitted by parts as the host sends GET RESPONSE commands.
+struct ResponseChain {
+    buffer: &'static mut [u8],
+    /// Length of the stored response.
+    len: usize,
+    /// Number of bytes of the stored response already transmitted.
+    offset: usize,
+    /// Status word to transmit with the last part of the response.
+    sw: u16,
+    /// Whether the response is encrypted by the secure channel, in which case it is only served
+    /// to GET RESPONSE commands received through the channel.
+    wrapped: bool,
+}
+
+impl ResponseChain {
+    fn reset(&mut self) {
+        self.len = 0;
+        self.offset = 0;
+    }
+
+    /// Returns true if a response is stored and has not been fully transmitted yet.
+    fn is_pending(&self) -> bool {
+        self.offset > 0 && self.offset < self.len
+    }
+
+    /// Copies the next part of the response, of at most `le` bytes, into `dest`.
+    /// Returns the length of the part and the status word to transmit with it.
+    fn next_part(&mut self, dest: &mut [u8], le: usize) -> (usize, u16) {
+        let len = (self.len - self.offset).min(le).min(dest.len());
+        dest[..len].copy_from_slice(&self.buffer[self.offset..self.offset + len]);
+        self.offset += len;
+
+        let remaining = self.len - self.offset;
+        if remaining == 0 {
+            let sw = self.sw;
+            self.reset();
+            (len, sw)
+        } else if remaining > 0xff {
+            (len, 0x6100)
+        } else {
+            (len, 0x6100 | remaining as u16)
+        }
+    }
 }
 
 impl Default for Comm {
@@ -137,14 +411,57 @@ pub struct ApduHeader {
 impl Comm {
     /// Creates a new [`Comm`] instance, which accepts any CLA APDU by default.
     pub const fn new() -> Self {
+        Self::with_transport(DefaultTransport::new())
+    }
+}
+
+impl<Tr: Transport> Comm<Tr> {
+    /// Creates a new [`Comm`] instance using the given [`Transport`], which accepts any CLA APDU
+    /// by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut comm = Comm::with_transport(MyTransport::new()).set_expected_cla(0xe0);
+    /// ```
+    pub const fn with_transport(transport: Tr) -> Self {
+        Self::with_buffer_size(transport)
+    }
+}
+
+impl<Tr: Transport, const N: usize> Comm<Tr, N> {
+    /// Fails to compile if the APDU buffer is smaller than [`MIN_APDU_BUFFER_SIZE`].
+    const BUFFER_SIZE_CHECK: () = assert!(N >= MIN_APDU_BUFFER_SIZE, "APDU buffer too small");
+
+    /// Creates a new [`Comm`] instance using the given [`Transport`] and an APDU buffer of `N`
+    /// bytes, which accepts any CLA APDU by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut comm = Comm::<_, 512>::with_buffer_size(DefaultTransport::new());
+    /// ```
+    pub const fn with_buffer_size(transport: Tr) -> Self {
+        let () = Self::BUFFER_SIZE_CHECK;
         Self {
-            apdu_buffer: [0u8; 260],
+            apdu_buffer: [0u8; N],
             rx: 0,
             tx: 0,
             event_pending: false,
-            #[cfg(not(any(target_os = "stax", target_os = "flex")))]
-            buttons: ButtonsState::new(),
+            transport,
             expected_cla: None,
+            chain: None,
+            response: None,
+            overflow: false,
+            legacy_apdu_parsing: false,
+            bolos_handler: None,
+            bolos_quit: true,
+            connection_events: false,
+            #[cfg(not(feature = "mock"))]
+            secure_channel: None,
+            lock_policy: LockPolicy::Ignore,
+            #[cfg(feature = "trace")]
+            trace: Trace::new(),
         }
     }
 
@@ -167,48 +484,241 @@ impl Comm {
         self
     }
 
+    /// Parses incoming APDUs with the rules used by former versions of the SDK (see
+    /// [`ApduCommand::parse_legacy`]) instead of ISO 7816-4.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut comm = Comm::new().set_legacy_apdu_parsing();
+    /// ```
+    pub fn set_legacy_apdu_parsing(mut self) -> Self {
+        self.legacy_apdu_parsing = true;
+        self
+    }
+
+    /// Registers a handler for application-specific BOLOS APDUs (CLA `0xb0`).
+    ///
+    /// The handler is called for every APDU with CLA `0xb0`, before the built-in handling of
+    /// GetAppInfo (INS `0x01`) and quit (INS `0xa7`) commands, which it can therefore override.
+    /// APDUs it does not handle fall back to the built-in handling.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Handler of application-specific BOLOS APDUs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// fn bolos_handler(comm: &mut Comm, header: ApduHeader) -> Option<Result<(), Reply>> {
+    ///     match header.ins {
+    ///         // Capability flags
+    ///         0x10 => {
+    ///             comm.append(&[0x01]);
+    ///             Some(Ok(()))
+    ///         }
+    ///         _ => None,
+    ///     }
+    /// }
+    ///
+    /// let mut comm = Comm::new().set_bolos_handler(bolos_handler);
+    /// ```
+    pub fn set_bolos_handler(mut self, handler: BolosHandler<Tr, N>) -> Self {
+        self.bolos_handler = Some(handler);
+        self
+    }
+
+    /// Enables or disables the built-in BOLOS quit command (INS `0xa7`), which exits the
+    /// application. When disabled, the command is rejected with [`StatusWords::BadIns`] unless
+    /// handled by the handler registered with [`Comm::set_bolos_handler`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut comm = Comm::new().set_bolos_quit(false);
+    /// ```
+    pub fn set_bolos_quit(mut self, enabled: bool) -> Self {
+        self.bolos_quit = enabled;
+        self
+    }
+
+    /// Enables or disables the reporting of connection and power state changes (USB reset,
+    /// suspend and resume, BLE connection and disconnection) as [`Event::Connection`] events.
+    /// These changes are handled by the SDK, and are not reported by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut comm = Comm::new().set_connection_events(true);
+    ///
+    /// match comm.next_event::<Instruction>() {
+    ///     Event::Connection(ConnectionEvent::UsbSuspend | ConnectionEvent::BleDisconnected) => {
+    ///         session.abort();
+    ///     }
+    ///     ...
+    /// }
+    /// ```
+    pub fn set_connection_events(mut self, enabled: bool) -> Self {
+        self.connection_events = enabled;
+        self
+    }
+
+    /// Enables the encrypted and authenticated channel `channel` (see [`SecureChannel`]).
+    ///
+    /// Commands received through the channel are decrypted before being surfaced as
+    /// [`Event::Command`], and their responses are encrypted by [`Comm::reply`]. Encrypted
+    /// responses are limited to [`SecureChannel::OVERHEAD`] bytes less than plaintext ones.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let identity = Secp256r1::derive_from_path(&IDENTITY_PATH);
+    /// let mut comm = Comm::new()
+    ///     .set_expected_cla(0xe0)
+    ///     .set_secure_channel(SecureChannel::new(0xe1, identity));
+    /// ```
+    #[cfg(not(feature = "mock"))]
+    pub fn set_secure_channel(mut self, channel: SecureChannel) -> Self {
+        self.secure_channel = Some(channel);
+        self
+    }
+
+    /// Returns the secure channel enabled with [`Comm::set_secure_channel`], if any.
+    #[cfg(not(feature = "mock"))]
+    pub fn secure_channel(&mut self) -> Option<&mut SecureChannel> {
+        self.secure_channel.as_mut()
+    }
+
+    /// Defines how commands received while the device is locked are processed (see
+    /// [`LockPolicy`]). By default, they are processed as if the device was unlocked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut comm = Comm::new().set_lock_policy(LockPolicy::ValidatePin);
+    /// ```
+    pub fn set_lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = policy;
+        self
+    }
+
+    /// Returns true if the command can be processed according to the lock policy, after
+    /// asking the user to validate the PIN if needed.
+    fn unlock(&mut self) -> bool {
+        if self.lock_policy == LockPolicy::Ignore || !self.transport.is_device_locked() {
+            return true;
+        }
+        match self.lock_policy {
+            LockPolicy::ValidatePin => {
+                self.transport.validate_pin();
+                !self.transport.is_device_locked()
+            }
+            _ => false,
+        }
+    }
+
+    /// Enables a command returning the [`Trace`] of the last APDUs to the host, with CLA `cla`
+    /// and INS `ins`.
+    ///
+    /// The response is the number of entries in the trace (1 byte), followed by the serialized
+    /// entries (see [`TraceEntry::serialize`]), from the `P1`-th oldest one, which fit in the
+    /// response.
+    #[cfg(feature = "trace")]
+    pub fn set_trace_command(mut self, cla: u8, ins: u8) -> Self {
+        self.trace.command = Some((cla, ins));
+        self
+    }
+
+    /// Returns the trace of the last APDUs exchanged.
+    #[cfg(feature = "trace")]
+    pub fn trace(&mut self) -> &mut Trace {
+        &mut self.trace
+    }
+
+    /// Enables ISO 7816-4 command chaining.
+    ///
+    /// APDUs with the chaining bit (`0x10`) set in their CLA byte are acknowledged automatically,
+    /// and their data is accumulated in `buffer`. The command is surfaced as an
+    /// [`Event::Command`] only when the last command of the chain (chaining bit cleared) is
+    /// received, and [`Comm::get_data`] then returns the whole reassembled data.
+    ///
+    /// All commands of a chain must have the same CLA, INS, P1 and P2 bytes, otherwise the chain
+    /// is aborted with [`StatusWords::LastCommandExpected`]. Chains exceeding the size of `buffer`
+    /// are aborted with [`StatusWords::BadLen`].
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Buffer used to reassemble the data of chained commands.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// static mut CHAIN_BUFFER: [u8; 1024] = [0u8; 1024];
+    ///
+    /// let mut comm = Comm::new()
+    ///     .set_expected_cla(0xe0)
+    ///     .set_chaining_buffer(unsafe { &mut *core::ptr::addr_of_mut!(CHAIN_BUFFER) });
+    /// ```
+    pub fn set_chaining_buffer(mut self, buffer: &'static mut [u8]) -> Self {
+        self.chain = Some(CommandChain {
+            buffer,
+            len: 0,
+            header: None,
+            complete: false,
+        });
+        self
+    }
+
+    /// Enables response chaining for responses larger than the APDU buffer.
+    ///
+    /// Once the data appended with [`Comm::append`] exceeds the APDU buffer, it is stored in
+    /// `buffer` instead. When replying, only the first part of the response is transmitted along
+    /// with a `61xx` status word, `xx` being the number of remaining bytes (`00` if 256 or more).
+    /// The host retrieves the next parts with GET RESPONSE commands (CLA `0x00` or the CLA set with
+    /// [`Comm::set_expected_cla`], INS `0xc0`, P1 = P2 = 0), each part being at most as long as
+    /// their short or extended Le. They are served automatically by [`Comm::next_event`] and are
+    /// not surfaced to the application. The status word given to [`Comm::reply`] is transmitted
+    /// with the last part.
+    ///
+    /// Any other command received while a response is pending discards the rest of the response.
+    /// The rest of a response to a command received through the secure channel is only served to
+    /// GET RESPONSE commands received through the channel: a plaintext GET RESPONSE discards it,
+    /// and is rejected with [`SyscallError::Security`].
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Buffer used to store large responses.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// static mut RESPONSE_BUFFER: [u8; 1024] = [0u8; 1024];
+    ///
+    /// let mut comm = Comm::new()
+    ///     .set_response_buffer(unsafe { &mut *core::ptr::addr_of_mut!(RESPONSE_BUFFER) });
+    /// ```
+    pub fn set_response_buffer(mut self, buffer: &'static mut [u8]) -> Self {
+        self.response = Some(ResponseChain {
+            buffer,
+            len: 0,
+            offset: 0,
+            sw: 0,
+            wrapped: false,
+        });
+        self
+    }
+
     /// Send the currently held APDU
     // This is private. Users should call reply to set the satus word and
     // transmit the response.
     fn apdu_send(&mut self) {
-        if !sys_seph::is_status_sent() {
-            sys_seph::send_general_status()
-        }
-        let mut spi_buffer = [0u8; 128];
-        while sys_seph::is_status_sent() {
-            sys_seph::seph_recv(&mut spi_buffer, 0);
-            seph::handle_event(&mut self.apdu_buffer, &spi_buffer);
-        }
-
-        match unsafe { G_io_app.apdu_state } {
-            APDU_USB_HID => unsafe {
-                ledger_secure_sdk_sys::io_usb_hid_send(
-                    Some(io_usb_send_apdu_data),
-                    self.tx as u16,
-                    self.apdu_buffer.as_mut_ptr(),
-                );
-            },
-            APDU_RAW => {
-                let len = (self.tx as u16).to_be_bytes();
-                sys_seph::seph_send(&[sys_seph::SephTags::RawAPDU as u8, len[0], len[1]]);
-                sys_seph::seph_send(&self.apdu_buffer[..self.tx]);
-            }
-            #[cfg(feature = "ccid")]
-            APDU_USB_CCID => {
-                ccid::send(&self.apdu_buffer[..self.tx]);
-            }
-            #[cfg(any(target_os = "nanox", target_os = "stax", target_os = "flex"))]
-            APDU_BLE => {
-                ble::send(&self.apdu_buffer[..self.tx]);
-            }
-            _ => (),
-        }
+        self.transport.send(&mut self.apdu_buffer, self.tx);
         self.tx = 0;
         self.rx = 0;
-        unsafe {
-            G_io_app.apdu_state = APDU_IDLE;
-            G_io_app.apdu_media = IO_APDU_MEDIA_NONE;
-            G_io_app.apdu_length = 0;
+        if let Some(chain) = self.chain.as_mut() {
+            if chain.complete {
+                chain.reset();
+            }
         }
     }
 
@@ -218,7 +728,8 @@ impl Comm {
     /// The conversion can embed complex parsing logic, including checks on CLA, INS, P1 and P2
     /// bytes, and may return an error with a status word for invalid APDUs.
     ///
-    /// In particular, it is recommended to use an enumeration for the possible INS values.
+    /// In particular, it is recommended to use an enumeration for the possible INS values. The
+    /// conversion of such an enumeration can be derived with [`ApduInstruction`].
     ///
     /// # Examples
     ///
@@ -255,21 +766,10 @@ impl Comm {
     {
         let mut spi_buffer = [0u8; 128];
 
-        unsafe {
-            G_io_app.apdu_state = APDU_IDLE;
-            G_io_app.apdu_media = IO_APDU_MEDIA_NONE;
-            G_io_app.apdu_length = 0;
-        }
+        self.transport.reset();
 
         loop {
-            // Signal end of command stream from SE to MCU
-            // And prepare reception
-            if !sys_seph::is_status_sent() {
-                sys_seph::send_general_status();
-            }
-
-            // Fetch the next message from the MCU
-            let _rx = sys_seph::seph_recv(&mut spi_buffer, 0);
+            self.transport.recv(&mut spi_buffer);
 
             if let Some(value) = self.decode_event(&mut spi_buffer) {
                 return value;
@@ -277,20 +777,64 @@ impl Comm {
         }
     }
 
-    pub fn next_event_ahead<T>(&mut self) -> bool
+    /// Wait and return next button press event or APDU command, for at most `ticks` ticker
+    /// events.
+    ///
+    /// This is the same as [`Comm::next_event_until`] with a new [`Deadline`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// match comm.next_event_timeout::<Instruction>(100) {
+    ///     Ok(Event::Command(ins)) => { ... }
+    ///     Ok(_) => { ... }
+    ///     Err(TimeoutError) => { /* Dismiss the screen */ }
+    /// }
+    /// ```
+    pub fn next_event_timeout<T>(&mut self, ticks: u32) -> Result<Event<T>, TimeoutError>
+    where
+        T: TryFrom<ApduHeader>,
+        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+    {
+        self.next_event_until(&mut Deadline::new(ticks))
+    }
+
+    /// Wait and return next button press event or APDU command, or [`TimeoutError`] once
+    /// `deadline` has expired.
+    ///
+    /// Ticker events are counted by `deadline` and are not returned. If `deadline` has already
+    /// expired, [`TimeoutError`] is returned without waiting.
+    pub fn next_event_until<T>(&mut self, deadline: &mut Deadline) -> Result<Event<T>, TimeoutError>
     where
         T: TryFrom<ApduHeader>,
         Reply: From<<T as TryFrom<ApduHeader>>::Error>,
     {
         let mut spi_buffer = [0u8; 128];
 
-        // Signal end of command stream from SE to MCU
-        // And prepare reception
-        if !sys_seph::is_status_sent() {
-            sys_seph::send_general_status();
+        self.transport.reset();
+
+        while !deadline.is_expired() {
+            self.transport.recv(&mut spi_buffer);
+
+            match self.decode_event(&mut spi_buffer) {
+                Some(Event::Ticker) => {
+                    deadline.tick();
+                }
+                Some(value) => return Ok(value),
+                None => (),
+            }
         }
-        // Fetch the next message from the MCU
-        let _rx = sys_seph::seph_recv(&mut spi_buffer, 0);
+        Err(TimeoutError)
+    }
+
+    pub fn next_event_ahead<T>(&mut self) -> bool
+    where
+        T: TryFrom<ApduHeader>,
+        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+    {
+        let mut spi_buffer = [0u8; 128];
+
+        self.transport.recv(&mut spi_buffer);
         return self.detect_apdu::<T>(&mut spi_buffer);
     }
 
@@ -301,35 +845,124 @@ impl Comm {
     {
         if self.event_pending {
             self.event_pending = false;
+            #[cfg(feature = "trace")]
+            self.trace.command(&self.apdu_buffer[..self.rx]);
+
             // Reject incomplete APDUs
             if self.rx < 4 {
                 self.reply(StatusWords::BadLen);
                 return None;
             }
 
-            // Check for data length by using `get_data`
-            if let Err(sw) = self.get_data() {
+            // Check for data length
+            if let Err(sw) = parse_apdu(&self.apdu_buffer[..self.rx], self.legacy_apdu_parsing) {
                 self.reply(sw);
                 return None;
             }
 
-            // Manage BOLOS specific APDUs B0xx0000
-            if self.apdu_buffer[0] == 0xB0
-                && self.apdu_buffer[2] == 0x00
-                && self.apdu_buffer[3] == 0x00
-            {
-                handle_bolos_apdu(self, self.apdu_buffer[1]);
+            // Check whether the device is locked before processing non-BOLOS commands
+            if self.apdu_buffer[0] != 0xB0 && !self.unlock() {
+                self.reply(StatusWords::DeviceLocked);
+                return None;
+            }
+
+            // Decrypt commands received through the secure channel
+            #[cfg(not(feature = "mock"))]
+            if let Some(channel) = self.secure_channel.as_mut() {
+                match channel.filter(&mut self.apdu_buffer, self.rx) {
+                    Incoming::Plain => (),
+                    Incoming::Unwrapped(rx) => {
+                        self.rx = rx;
+                        let apdu = &self.apdu_buffer[..self.rx];
+                        if let Err(sw) = parse_apdu(apdu, self.legacy_apdu_parsing) {
+                            self.reply(sw);
+                            return None;
+                        }
+                    }
+                    Incoming::Handled(tx, reply) => {
+                        self.tx = tx;
+                        self.send_sw(reply.0);
+                        return None;
+                    }
+                }
+            }
+
+            // Serve GET RESPONSE commands from the pending response, if any.
+            // Any other command discards the pending response.
+            let capacity = self.apdu_capacity();
+            let wrapping = self.is_wrapping();
+            let get_response = self.get_response_le();
+            if let Some(response) = self.response.as_mut() {
+                if let Some(le) = get_response.filter(|_| response.is_pending()) {
+                    // Responses are only served through the channel they have been built for
+                    if response.wrapped != wrapping {
+                        response.reset();
+                        self.reply(SyscallError::Security);
+                        return None;
+                    }
+                    let (len, sw) = response.next_part(&mut self.apdu_buffer, le.min(capacity));
+                    self.tx = len;
+                    self.send_sw(sw);
+                    return None;
+                }
+                response.reset();
+            }
+
+            // Manage BOLOS specific APDUs B0xxxxxx, application handler first
+            if self.apdu_buffer[0] == 0xB0 {
+                if let Some(handler) = self.bolos_handler {
+                    let header = *self.get_apdu_metadata();
+                    if let Some(result) = handler(self, header) {
+                        self.reply_result(result);
+                        return None;
+                    }
+                }
+                #[cfg(not(feature = "mock"))]
+                if self.apdu_buffer[2] == 0x00 && self.apdu_buffer[3] == 0x00 {
+                    handle_bolos_apdu(self, self.apdu_buffer[1]);
+                    return None;
+                }
+            }
+
+            // Return the trace to the host
+            #[cfg(feature = "trace")]
+            if self.trace.command == Some((self.apdu_buffer[0], self.apdu_buffer[1])) {
+                let first = self.apdu_buffer[2] as usize;
+                let capacity = self.apdu_capacity();
+                self.tx = self
+                    .trace
+                    .serialize(first, &mut self.apdu_buffer[..capacity]);
+                self.reply_ok();
                 return None;
             }
 
             // If CLA filtering is enabled, automatically reject APDUs with wrong CLA
             if let Some(cla) = self.expected_cla {
-                if self.apdu_buffer[0] != cla {
+                let mask = match self.chain {
+                    Some(_) => !CLA_CHAINING,
+                    None => 0xff,
+                };
+                if self.apdu_buffer[0] & mask != cla {
                     self.reply(StatusWords::BadCla);
                     return None;
                 }
             }
 
+            // Reassemble chained commands, only the last one is surfaced
+            if let Some(chain) = self.chain.as_mut() {
+                match chain.push(&self.apdu_buffer[..self.rx], self.legacy_apdu_parsing) {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        self.reply_ok();
+                        return None;
+                    }
+                    Err(sw) => {
+                        self.reply(sw);
+                        return None;
+                    }
+                }
+            }
+
             let res = T::try_from(*self.get_apdu_metadata());
             match res {
                 Ok(ins) => {
@@ -351,68 +984,15 @@ impl Comm {
         T: TryFrom<ApduHeader>,
         Reply: From<<T as TryFrom<ApduHeader>>::Error>,
     {
-        // message = [ tag, len_hi, len_lo, ... ]
-        let tag = spi_buffer[0];
-        let len = u16::from_be_bytes([spi_buffer[1], spi_buffer[2]]);
-
-        // XXX: check whether this is necessary
-        // if rx < 3 && rx != len+3 {
-        //     unsafe {
-        //        G_io_app.apdu_state = APDU_IDLE;
-        //        G_io_app.apdu_length = 0;
-        //     }
-        //     return None
-        // }
-
-        // Treat all possible events.
-        // If this is a button push, return with the associated event
-        // If this is an APDU, return with the "received command" event
-        // Any other event (usb, xfer, ticker) is silently handled
-        match seph::Events::from(tag) {
-            #[cfg(not(any(target_os = "stax", target_os = "flex")))]
-            seph::Events::ButtonPush => {
-                let button_info = spi_buffer[3] >> 1;
-                if let Some(btn_evt) = get_button_event(&mut self.buttons, button_info) {
-                    return Some(Event::Button(btn_evt));
-                }
-            }
-            seph::Events::USBEvent => {
-                if len == 1 {
-                    seph::handle_usb_event(spi_buffer[3]);
-                }
-            }
-            seph::Events::USBXFEREvent => {
-                if len >= 3 {
-                    seph::handle_usb_ep_xfer_event(&mut self.apdu_buffer, spi_buffer);
-                }
-            }
-            seph::Events::CAPDUEvent => seph::handle_capdu_event(&mut self.apdu_buffer, spi_buffer),
-
-            #[cfg(any(target_os = "nanox", target_os = "stax", target_os = "flex"))]
-            seph::Events::BleReceive => ble::receive(&mut self.apdu_buffer, spi_buffer),
-
-            seph::Events::TickerEvent => {
-                #[cfg(any(target_os = "stax", target_os = "flex"))]
-                unsafe {
-                    ux_process_ticker_event();
-                }
-                return Some(Event::Ticker);
-            }
-
-            #[cfg(any(target_os = "stax", target_os = "flex"))]
-            seph::Events::ScreenTouch => unsafe {
-                ux_process_finger_event(spi_buffer.as_mut_ptr());
-                return Some(Event::TouchEvent);
-            },
-
-            _ => {
-                #[cfg(any(target_os = "stax", target_os = "flex"))]
-                unsafe {
-                    ux_process_default_event();
-                }
+        match self.transport.process(spi_buffer, &mut self.apdu_buffer) {
+            Some(Event::Connection(_)) if !self.connection_events => None,
+            #[cfg(feature = "trace")]
+            Some(Event::Ticker) => {
+                self.trace.tick();
+                Some(Event::Ticker)
             }
+            event => event.map(cast_event),
         }
-        None
     }
 
     pub fn decode_event<T>(&mut self, spi_buffer: &mut [u8; 128]) -> Option<Event<T>>
@@ -424,8 +1004,8 @@ impl Comm {
             return Some(event);
         }
 
-        if unsafe { G_io_app.apdu_state } != APDU_IDLE && unsafe { G_io_app.apdu_length } > 0 {
-            self.rx = unsafe { G_io_app.apdu_length as usize };
+        if let Some(len) = self.transport.received() {
+            self.rx = len;
             self.event_pending = true;
             return self.check_event();
         }
@@ -439,8 +1019,8 @@ impl Comm {
     {
         let _: Option<Event<T>> = self.decode_event(spi_buffer);
 
-        if unsafe { G_io_app.apdu_state } != APDU_IDLE && unsafe { G_io_app.apdu_length } > 0 {
-            self.rx = unsafe { G_io_app.apdu_length as usize };
+        if let Some(len) = self.transport.received() {
+            self.rx = len;
             self.event_pending = true;
             return true;
         }
@@ -494,13 +1074,62 @@ impl Comm {
     /// Set the Status Word of the response to the previous Command event, and
     /// transmit the response.
     ///
+    /// If response chaining is enabled and the response exceeds the APDU buffer, only its first
+    /// part is transmitted (see [`Comm::set_response_buffer`]).
+    ///
     /// # Arguments
     ///
     /// * `sw` - Status Word to be transmitted after the Data. Can be a
     ///   StatusWords, a SyscallError, or any type which can be converted to a
     ///   Reply.
     pub fn reply<T: Into<Reply>>(&mut self, reply: T) {
-        let sw = reply.into().0;
+        let mut sw = reply.into().0;
+        if self.overflow {
+            self.overflow = false;
+            self.tx = 0;
+            if let Some(response) = self.response.as_mut() {
+                response.reset();
+            }
+            sw = Reply::from(SyscallError::Overflow).0;
+        }
+        let capacity = self.apdu_capacity();
+        let wrapping = self.is_wrapping();
+        if let Some(response) = self.response.as_mut() {
+            if response.len > 0 {
+                response.sw = sw;
+                response.wrapped = wrapping;
+                let le = RESPONSE_CHUNK_MAX.min(capacity);
+                let (len, sw) = response.next_part(&mut self.apdu_buffer, le);
+                self.tx = len;
+                self.send_sw(sw);
+                return;
+            }
+        }
+        self.send_sw(sw);
+    }
+
+    /// Append the status word to the data held in the APDU buffer, and transmit the response.
+    ///
+    /// The response to a command received through the secure channel is encrypted first.
+    fn send_sw(&mut self, sw: u16) {
+        #[cfg(not(feature = "mock"))]
+        let sw = match self.secure_channel.as_mut() {
+            Some(channel) if channel.is_wrapping() => {
+                match channel.wrap(&mut self.apdu_buffer, self.tx, sw) {
+                    Ok(len) => {
+                        self.tx = len;
+                        StatusWords::Ok as u16
+                    }
+                    Err(reply) => {
+                        self.tx = 0;
+                        reply.0
+                    }
+                }
+            }
+            _ => sw,
+        };
+        #[cfg(feature = "trace")]
+        self.trace.response(self.tx, sw);
         // Append status word
         self.apdu_buffer[self.tx] = (sw >> 8) as u8;
         self.apdu_buffer[self.tx + 1] = sw as u8;
@@ -515,6 +1144,33 @@ impl Comm {
         self.reply(StatusWords::Ok);
     }
 
+    /// Transmit the response with [`StatusWords::Ok`] if `result` is `Ok`, or with the status
+    /// word of the error otherwise.
+    ///
+    /// This allows writing APDU handlers returning a `Result`, in which errors are propagated
+    /// with `?`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// fn handle_get_version(comm: &mut Comm) -> Result<(), Reply> {
+    ///     let mut writer = comm.writer();
+    ///     writer.put_slice(&[1, 0, 0])?;
+    ///     Ok(())
+    /// }
+    ///
+    /// if let Event::Command(Instruction::GetVersion) = comm.next_event() {
+    ///     let result = handle_get_version(&mut comm);
+    ///     comm.reply_result(result);
+    /// }
+    /// ```
+    pub fn reply_result<E: Into<Reply>>(&mut self, result: Result<(), E>) {
+        match result {
+            Ok(()) => self.reply_ok(),
+            Err(e) => self.reply(e),
+        }
+    }
+
     /// Return APDU Metadata
     pub fn get_apdu_metadata(&self) -> &ApduHeader {
         assert!(self.apdu_buffer.len() >= 4);
@@ -522,78 +1178,184 @@ impl Comm {
         unsafe { &*ptr }
     }
 
-    pub fn get_data(&self) -> Result<&[u8], StatusWords> {
-        if self.rx == 4 {
-            Ok(&[]) // Conforming zero-data APDU
-        } else {
-            let first_len_byte = self.apdu_buffer[4] as usize;
-            let get_data_from_buffer = |len, offset| {
-                if len == 0 || len + offset > self.rx {
-                    Err(StatusWords::BadLen)
-                } else {
-                    Ok(&self.apdu_buffer[offset..offset + len])
-                }
-            };
-            match (first_len_byte, self.rx) {
-                (0, 5) => Ok(&[]), // Non-conforming zero-data APDU
-                (0, 6) => Err(StatusWords::BadLen),
-                (0, _) => {
-                    let len =
-                        u16::from_le_bytes([self.apdu_buffer[5], self.apdu_buffer[6]]) as usize;
-                    get_data_from_buffer(len, 7)
-                }
-                (len, _) => get_data_from_buffer(len, 5),
+    /// Returns a parsed view of the current command.
+    ///
+    /// APDUs are parsed following ISO 7816-4 (see [`ApduCommand::parse`]), unless legacy parsing
+    /// has been enabled with [`Comm::set_legacy_apdu_parsing`].
+    ///
+    /// If command chaining is enabled and the current command is the last one of a chain, the
+    /// data of the returned command is the reassembled data of the whole chain.
+    pub fn get_command(&self) -> Result<ApduCommand<'_>, StatusWords> {
+        let mut command = parse_apdu(&self.apdu_buffer[..self.rx], self.legacy_apdu_parsing)?;
+        if let Some(chain) = &self.chain {
+            if chain.complete {
+                command.data = &chain.buffer[..chain.len];
+                command.lc = chain.len;
             }
         }
+        Ok(command)
+    }
+
+    /// Returns the data of the current command (see [`Comm::get_command`]).
+    pub fn get_data(&self) -> Result<&[u8], StatusWords> {
+        self.get_command().map(|command| command.data)
+    }
+
+    /// Returns an [`ApduReader`] over the data of the current command.
+    pub fn reader(&self) -> Result<ApduReader<'_>, StatusWords> {
+        self.get_data().map(ApduReader::new)
     }
 
     pub fn get(&self, start: usize, end: usize) -> &[u8] {
         &self.apdu_buffer[start..end]
     }
 
+    /// Returns an [`ApduWriter`] to build the response.
+    pub fn writer(&mut self) -> ApduWriter<'_, Tr, N> {
+        ApduWriter::new(self)
+    }
+
+    /// Returns the length of the response data appended so far.
+    fn response_len(&self) -> usize {
+        match &self.response {
+            Some(response) if response.len > 0 => response.len,
+            _ => self.tx,
+        }
+    }
+
+    /// Returns true if the response to the current command is encrypted by the secure channel.
+    fn is_wrapping(&self) -> bool {
+        #[cfg(not(feature = "mock"))]
+        if let Some(channel) = &self.secure_channel {
+            return channel.is_wrapping();
+        }
+        false
+    }
+
+    /// Returns the maximum length of the response data expected by the command held in the APDU
+    /// buffer if it is a GET RESPONSE command: CLA `0x00` or the expected CLA, INS `0xc0`, P1 = P2
+    /// = 0 and no data.
+    fn get_response_le(&self) -> Option<usize> {
+        let apdu = ApduCommand::parse(&self.apdu_buffer[..self.rx]).ok()?;
+        let header = apdu.header;
+        let is_get_response = (header.cla == 0x00 || Some(header.cla) == self.expected_cla)
+            && header.ins == INS_GET_RESPONSE
+            && header.p1 == 0x00
+            && header.p2 == 0x00
+            && apdu.lc == 0;
+        is_get_response.then(|| apdu.le.unwrap_or(RESPONSE_CHUNK_MAX))
+    }
+
+    /// Returns the maximum length of the response data held in the APDU buffer, keeping two bytes
+    /// for the status word, and room for the encryption of the response if needed.
+    fn apdu_capacity(&self) -> usize {
+        let capacity = self.apdu_buffer.len() - 2;
+        #[cfg(not(feature = "mock"))]
+        if self.is_wrapping() {
+            return capacity - SecureChannel::OVERHEAD;
+        }
+        capacity
+    }
+
+    /// Returns the maximum length of the response data, keeping two bytes for the status word.
+    fn response_capacity(&self) -> usize {
+        let capacity = self.apdu_capacity();
+        match &self.response {
+            Some(response) => response.buffer.len().max(capacity),
+            None => capacity,
+        }
+    }
+
+    /// Append data to the response.
+    ///
+    /// If the data does not fit in the APDU buffer, or in the response buffer when response
+    /// chaining is enabled, it is dropped and the response is replaced by
+    /// [`SyscallError::Overflow`] when transmitted.
     pub fn append(&mut self, m: &[u8]) {
-        for c in m.iter() {
-            self.apdu_buffer[self.tx] = *c;
-            self.tx += 1;
+        if self.overflow {
+            return;
         }
+        let capacity = self.apdu_capacity();
+        if let Some(response) = self.response.as_mut() {
+            if response.len > 0 || self.tx + m.len() > capacity {
+                let len = response.len.max(self.tx);
+                if len + m.len() > response.buffer.len() {
+                    self.overflow = true;
+                    return;
+                }
+                // Move the response to the response buffer once it exceeds the APDU buffer
+                if response.len == 0 {
+                    response.buffer[..self.tx].copy_from_slice(&self.apdu_buffer[..self.tx]);
+                    response.len = self.tx;
+                }
+                response.buffer[response.len..response.len + m.len()].copy_from_slice(m);
+                response.len += m.len();
+                return;
+            }
+        }
+        if self.tx + m.len() > capacity {
+            self.overflow = true;
+            return;
+        }
+        self.apdu_buffer[self.tx..self.tx + m.len()].copy_from_slice(m);
+        self.tx += m.len();
+    }
+}
+
+/// Parses a raw APDU, with the rules of former SDK versions if `legacy` is set.
+fn parse_apdu(apdu: &[u8], legacy: bool) -> Result<ApduCommand<'_>, StatusWords> {
+    if legacy {
+        ApduCommand::parse_legacy(apdu)
+    } else {
+        ApduCommand::parse(apdu)
     }
 }
 
 // BOLOS APDU Handling (see https://developers.ledger.com/docs/connectivity/ledgerJS/open-close-info-on-apps)
-fn handle_bolos_apdu(com: &mut Comm, ins: u8) {
+#[cfg(not(feature = "mock"))]
+fn handle_bolos_apdu<Tr: Transport, const N: usize>(com: &mut Comm<Tr, N>, ins: u8) {
     match ins {
         // Get Information INS: retrieve App name and version
         0x01 => {
-            unsafe {
-                com.apdu_buffer[0] = 0x01;
-                com.tx += 1;
-                let len = os_registry_get_current_app_tag(
-                    BOLOS_TAG_APPNAME,
-                    &mut com.apdu_buffer[com.tx + 1] as *mut u8,
-                    (260 - com.tx - 1) as u32,
-                );
+            // Mandatory fields are truncated and optional ones dropped if they do not fit,
+            // keeping room for the status word and the encryption of the response
+            let capacity = com.apdu_capacity();
+            com.apdu_buffer[0] = 0x01;
+            com.tx = 1;
+            // Room kept for the fields after each of them: length of the version, and flags
+            for (tag, reserved) in [(BOLOS_TAG_APPNAME, 3), (BOLOS_TAG_APPVERSION, 2)] {
+                let max = (capacity - com.tx - 1 - reserved).min(0xff);
+                let len = unsafe {
+                    os_registry_get_current_app_tag(
+                        tag,
+                        com.apdu_buffer[com.tx + 1..].as_mut_ptr(),
+                        max as u32,
+                    )
+                };
+                let len = (len as usize).min(max);
                 com.apdu_buffer[com.tx] = len as u8;
-                com.tx += (1 + len) as usize;
+                com.tx += 1 + len;
+            }
 
-                let len = os_registry_get_current_app_tag(
-                    BOLOS_TAG_APPVERSION,
-                    &mut com.apdu_buffer[com.tx + 1] as *mut u8,
-                    (260 - com.tx - 1) as u32,
-                );
-                com.apdu_buffer[com.tx] = len as u8;
-                com.tx += (1 + len) as usize;
-
-                // to be fixed within io tasks
-                // return OS flags to notify of platform's global state (pin lock etc)
-                com.apdu_buffer[com.tx] = 1; // flags length
-                com.tx += 1;
-                com.apdu_buffer[com.tx] = os_flags() as u8;
-                com.tx += 1;
+            // to be fixed within io tasks
+            // return OS flags to notify of platform's global state (pin lock etc)
+            com.apdu_buffer[com.tx] = 1; // flags length
+            com.apdu_buffer[com.tx + 1] = unsafe { os_flags() } as u8;
+            com.tx += 2;
+
+            // Versions of the Rust SDK and of the C SDK, appended after the fields
+            // above so that existing parsers keep working
+            for info in [crate::RUST_SDK_VERSION, C_SDK_VERSION, C_SDK_HASH] {
+                if com.tx + 1 + info.len() > capacity {
+                    break;
+                }
+                com.append(&[info.len() as u8]);
+                com.append(info.as_bytes());
             }
             com.reply_ok();
         }
         // Quit Application INS
-        0xa7 => {
+        0xa7 if com.bolos_quit => {
             com.reply_ok();
             crate::exit_app(0);
         }
@@ -603,14 +1365,14 @@ fn handle_bolos_apdu(com: &mut Comm, ins: u8) {
     }
 }
 
-impl Index<usize> for Comm {
+impl<Tr, const N: usize> Index<usize> for Comm<Tr, N> {
     type Output = u8;
     fn index(&self, idx: usize) -> &Self::Output {
         &self.apdu_buffer[idx]
     }
 }
 
-impl IndexMut<usize> for Comm {
+impl<Tr, const N: usize> IndexMut<usize> for Comm<Tr, N> {
     fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
         self.tx = idx.max(self.tx);
         &mut self.apdu_buffer[idx]
@@ -634,4 +1396,192 @@ mod test {
         assert_eq!(m.p1, 0);
         assert_eq!(m.p2, 0);
     }
+
+    #[test]
+    fn derive_instruction() {
+        // The generated code must not depend on the macros in scope
+        #[allow(unused_macros)]
+        macro_rules! matches {
+            ($($t:tt)*) => {
+                compile_error!("shadowed")
+            };
+        }
+
+        #[derive(ApduInstruction, Debug, PartialEq)]
+        #[apdu(cla = 0xe0)]
+        enum Instruction {
+            #[apdu(ins = 0x03, p1 = 0, p2 = 0)]
+            GetVersion,
+            #[apdu(ins = 0x06, p1 = 0x00 | 0x80)]
+            SignTx,
+            #[apdu(cla = 0xb0, ins = 0x06)]
+            Other,
+        }
+
+        let header = |cla, ins, p1, p2| ApduHeader { cla, ins, p1, p2 };
+        assert_eq!(
+            Instruction::try_from(header(0xe0, 0x03, 0, 0)),
+            Ok(Instruction::GetVersion)
+        );
+        assert_eq!(
+            Instruction::try_from(header(0xe0, 0x06, 0x80, 0x12)),
+            Ok(Instruction::SignTx)
+        );
+        assert_eq!(
+            Instruction::try_from(header(0xb0, 0x06, 0x01, 0x00)),
+            Ok(Instruction::Other)
+        );
+        assert_eq!(
+            Instruction::try_from(header(0xe0, 0x03, 1, 0)),
+            Err(StatusWords::BadP1P2)
+        );
+        assert_eq!(
+            Instruction::try_from(header(0xe1, 0x06, 0, 0)),
+            Err(StatusWords::BadCla)
+        );
+        assert_eq!(
+            Instruction::try_from(header(0xe0, 0x07, 0, 0)),
+            Err(StatusWords::BadIns)
+        );
+    }
+
+    #[test]
+    fn command_chaining() {
+        static mut BUFFER: [u8; 8] = [0u8; 8];
+        let mut chain = CommandChain {
+            buffer: unsafe { &mut *core::ptr::addr_of_mut!(BUFFER) },
+            len: 0,
+            header: None,
+            complete: false,
+        };
+
+        // Unchained command outside of a chain is left untouched
+        assert_eq!(
+            chain.push(&[0xe0, 0x02, 0x00, 0x00, 0x01, 0xaa], false),
+            Ok(true)
+        );
+        assert_eq!(chain.len, 0);
+
+        assert_eq!(
+            chain.push(&[0xf0, 0x02, 0x00, 0x00, 0x02, 0x01, 0x02], false),
+            Ok(false)
+        );
+        assert_eq!(
+            chain.push(&[0xe0, 0x02, 0x00, 0x00, 0x02, 0x03, 0x04], false),
+            Ok(true)
+        );
+        assert_eq!(chain.complete, true);
+        assert_eq!(&chain.buffer[..chain.len], &[0x01, 0x02, 0x03, 0x04]);
+        chain.reset();
+
+        // Out-of-order command aborts the chain
+        assert_eq!(
+            chain.push(&[0xf0, 0x02, 0x00, 0x00, 0x01, 0x01], false),
+            Ok(false)
+        );
+        assert_eq!(
+            chain.push(&[0xe0, 0x04, 0x00, 0x00, 0x01, 0x01], false),
+            Err(StatusWords::LastCommandExpected)
+        );
+        assert_eq!(chain.header.is_none(), true);
+
+        // Oversized chain is rejected
+        assert_eq!(
+            chain.push(&[0xf0, 0x02, 0x00, 0x00, 0x05, 0, 0, 0, 0, 0], false),
+            Ok(false)
+        );
+        assert_eq!(
+            chain.push(&[0xe0, 0x02, 0x00, 0x00, 0x05, 0, 0, 0, 0, 0], false),
+            Err(StatusWords::BadLen)
+        );
+    }
+
+    #[test]
+    fn response_chaining() {
+        static mut BUFFER: [u8; 600] = [0u8; 600];
+        let mut response = ResponseChain {
+            buffer: unsafe { &mut *core::ptr::addr_of_mut!(BUFFER) },
+            len: 600,
+            offset: 0,
+            sw: 0x9000,
+            wrapped: false,
+        };
+        let mut dest = [0u8; 260];
+
+        assert_eq!(
+            response.next_part(&mut dest, RESPONSE_CHUNK_MAX),
+            (256, 0x6100)
+        );
+        assert_eq!(response.is_pending(), true);
+        assert_eq!(response.next_part(&mut dest, 0x10), (0x10, 0x6100));
+        assert_eq!(
+            response.next_part(&mut dest, RESPONSE_CHUNK_MAX),
+            (256, 0x6148)
+        );
+        assert_eq!(
+            response.next_part(&mut dest, RESPONSE_CHUNK_MAX),
+            (0x48, 0x9000)
+        );
+        assert_eq!(response.is_pending(), false);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn get_response() {
+        static mut RESPONSE_BUFFER: [u8; 1024] = [0u8; 1024];
+        let mut comm = Comm::<_, 600>::with_buffer_size(MockTransport::new())
+            .set_expected_cla(0xe0)
+            .set_response_buffer(unsafe { &mut *core::ptr::addr_of_mut!(RESPONSE_BUFFER) });
+        comm.transport.push_command(&[0xe0, 0x01, 0x00, 0x00]);
+        // Extended Le, then the CLA of the application
+        comm.transport
+            .push_command(&[0x00, 0xc0, 0x00, 0x00, 0x00, 0x02, 0x00]);
+        comm.transport.push_command(&[0xe0, 0xc0, 0x00, 0x00, 0x10]);
+        // Commands with another CLA are not GET RESPONSE, and discard the response
+        comm.transport.push_command(&[0xe1, 0xc0, 0x00, 0x00, 0x10]);
+        comm.transport.push_command(&[0x00, 0xc0, 0x00, 0x00, 0x10]);
+        comm.transport.push_ticker();
+
+        comm.next_event::<ApduHeader>();
+        comm.append(&[0xaa; 1000]);
+        comm.reply_ok();
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+
+        let response = comm.transport.pop_response().ok_or(())?;
+        assert_eq!(response.len(), 256 + 2);
+        assert_eq!(response[256..], [0x61, 0x00]);
+        let response = comm.transport.pop_response().ok_or(())?;
+        assert_eq!(response.len(), 512 + 2);
+        assert_eq!(response[512..], [0x61, 0xe8]);
+        let response = comm.transport.pop_response().ok_or(())?;
+        assert_eq!(response.len(), 16 + 2);
+        assert_eq!(response[16..], [0x61, 0xd8]);
+        let sw = (StatusWords::BadCla as u16).to_be_bytes();
+        assert_eq!(comm.transport.pop_response().as_deref(), Some(&sw[..]));
+        assert_eq!(comm.transport.pop_response().as_deref(), Some(&sw[..]));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn append_overflow() {
+        let mut comm = Comm::new();
+        comm.transport.push_command(&[0xe0, 0x01, 0x00, 0x00]);
+        comm.transport.push_command(&[0xe0, 0x01, 0x00, 0x00]);
+
+        comm.next_event::<ApduHeader>();
+        comm.append(&[0xaa; 200]);
+        comm.append(&[0xbb; 100]);
+        comm.reply_ok();
+        let sw = Reply::from(SyscallError::Overflow).0.to_be_bytes();
+        assert_eq!(comm.transport.pop_response().as_deref(), Some(&sw[..]));
+
+        // The next response is not affected
+        comm.next_event::<ApduHeader>();
+        comm.append(&[0xaa; 258]);
+        comm.reply_ok();
+        let response = comm.transport.pop_response().ok_or(())?;
+        assert_eq!(response.len(), 260);
+        assert_eq!(response[258..], [0x90, 0x00]);
+    }
 }
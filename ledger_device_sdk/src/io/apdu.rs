@@ -0,0 +1,223 @@
+//! Parsing of ISO 7816-4 command APDUs.
+
+use super::{ApduHeader, StatusWords};
+
+/// ISO 7816-4 command APDU cases.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApduCase {
+    /// No command data, no response data expected.
+    Case1,
+    /// No command data, response data expected.
+    Case2,
+    /// Command data, no response data expected.
+    Case3,
+    /// Command data, response data expected.
+    Case4,
+}
+
+/// Parsed view of a command APDU.
+#[derive(Copy, Clone)]
+pub struct ApduCommand<'a> {
+    /// CLA, INS, P1 and P2 bytes
+    pub header: ApduHeader,
+    /// Length of the command data (Nc)
+    pub lc: usize,
+    /// Command data
+    pub data: &'a [u8],
+    /// Maximum length of the expected response data (Ne), if any.
+    /// An encoded Le of zero is decoded as 256 (short) or 65536 (extended).
+    pub le: Option<usize>,
+    /// Whether the extended length encoding is used
+    pub extended: bool,
+}
+
+impl<'a> ApduCommand<'a> {
+    /// Parses a command APDU following ISO 7816-4, supporting cases 1 to 4 with both short and
+    /// extended length encodings.
+    ///
+    /// Returns [`StatusWords::BadLen`] if the APDU length is not consistent with its Lc and Le
+    /// fields.
+    ///
+    /// # Arguments
+    ///
+    /// * `apdu` - Raw APDU, starting with the CLA byte.
+    pub fn parse(apdu: &'a [u8]) -> Result<Self, StatusWords> {
+        let (header, body) = split_header(apdu)?;
+        let (extended, data, le) = match *body {
+            // Case 1
+            [] => (false, body, None),
+            // Case 2S
+            [le] => (false, &body[..0], Some(short_le(le))),
+            // Case 2E
+            [0, le_hi, le_lo] => (true, &body[..0], Some(extended_le(le_hi, le_lo))),
+            // Cases 3E and 4E
+            [0, lc_hi, lc_lo, ref rest @ ..] => {
+                let lc = u16::from_be_bytes([lc_hi, lc_lo]) as usize;
+                if lc == 0 {
+                    return Err(StatusWords::BadLen);
+                } else if rest.len() == lc {
+                    (true, rest, None)
+                } else if rest.len() == lc + 2 {
+                    let le = extended_le(rest[lc], rest[lc + 1]);
+                    (true, &rest[..lc], Some(le))
+                } else {
+                    return Err(StatusWords::BadLen);
+                }
+            }
+            [0, _] => return Err(StatusWords::BadLen),
+            // Cases 3S and 4S
+            [lc, ref rest @ ..] => {
+                let lc = lc as usize;
+                if rest.len() == lc {
+                    (false, rest, None)
+                } else if rest.len() == lc + 1 {
+                    (false, &rest[..lc], Some(short_le(rest[lc])))
+                } else {
+                    return Err(StatusWords::BadLen);
+                }
+            }
+        };
+        Ok(ApduCommand {
+            header,
+            lc: data.len(),
+            data,
+            le,
+            extended,
+        })
+    }
+
+    /// Parses a command APDU with the rules used by former versions of the SDK: the Le field is
+    /// not supported, the extended length is read as little-endian, and trailing bytes after the
+    /// command data are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `apdu` - Raw APDU, starting with the CLA byte.
+    pub fn parse_legacy(apdu: &'a [u8]) -> Result<Self, StatusWords> {
+        let (header, body) = split_header(apdu)?;
+        let get_data = |len: usize, offset: usize| {
+            if len == 0 || len + offset > body.len() {
+                Err(StatusWords::BadLen)
+            } else {
+                Ok(&body[offset..offset + len])
+            }
+        };
+        let (extended, data) = match *body {
+            // Conforming zero-data APDU
+            [] => (false, body),
+            // Non-conforming zero-data APDU
+            [0] => (false, &body[..0]),
+            [0, _] => return Err(StatusWords::BadLen),
+            [0, len_lo, len_hi, ..] => {
+                let len = u16::from_le_bytes([len_lo, len_hi]) as usize;
+                (true, get_data(len, 3)?)
+            }
+            [len, ..] => (false, get_data(len as usize, 1)?),
+        };
+        Ok(ApduCommand {
+            header,
+            lc: data.len(),
+            data,
+            le: None,
+            extended,
+        })
+    }
+
+    /// Returns the ISO 7816-4 case of the command.
+    pub fn case(&self) -> ApduCase {
+        match (self.lc > 0, self.le.is_some()) {
+            (false, false) => ApduCase::Case1,
+            (false, true) => ApduCase::Case2,
+            (true, false) => ApduCase::Case3,
+            (true, true) => ApduCase::Case4,
+        }
+    }
+}
+
+/// Splits a raw APDU into its header and the remaining bytes.
+fn split_header(apdu: &[u8]) -> Result<(ApduHeader, &[u8]), StatusWords> {
+    match *apdu {
+        [cla, ins, p1, p2, ref body @ ..] => Ok((ApduHeader { cla, ins, p1, p2 }, body)),
+        _ => Err(StatusWords::BadLen),
+    }
+}
+
+fn short_le(le: u8) -> usize {
+    match le {
+        0 => 256,
+        le => le as usize,
+    }
+}
+
+fn extended_le(le_hi: u8, le_lo: u8) -> usize {
+    match u16::from_be_bytes([le_hi, le_lo]) {
+        0 => 65536,
+        le => le as usize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn parse_short() {
+        let c = ApduCommand::parse(&[0xe0, 0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(c.case(), ApduCase::Case1);
+
+        let c = ApduCommand::parse(&[0xe0, 0x01, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(c.case(), ApduCase::Case2);
+        assert_eq!(c.le, Some(256));
+
+        let c = ApduCommand::parse(&[0xe0, 0x02, 0x00, 0x00, 0x02, 0xaa, 0xbb]).unwrap();
+        assert_eq!(c.case(), ApduCase::Case3);
+        assert_eq!(c.data, &[0xaa, 0xbb]);
+
+        let c = ApduCommand::parse(&[0xe0, 0x02, 0x00, 0x00, 0x01, 0xaa, 0x20]).unwrap();
+        assert_eq!(c.case(), ApduCase::Case4);
+        assert_eq!(c.data, &[0xaa]);
+        assert_eq!(c.le, Some(0x20));
+
+        assert_eq!(
+            ApduCommand::parse(&[0xe0, 0x02, 0x00, 0x00, 0x03, 0xaa]).err(),
+            Some(StatusWords::BadLen)
+        );
+    }
+
+    #[test]
+    fn parse_extended() {
+        let c = ApduCommand::parse(&[0xe0, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(c.case(), ApduCase::Case2);
+        assert_eq!(c.extended, true);
+        assert_eq!(c.le, Some(65536));
+
+        let c =
+            ApduCommand::parse(&[0xe0, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0xaa, 0xbb]).unwrap();
+        assert_eq!(c.case(), ApduCase::Case3);
+        assert_eq!(c.data, &[0xaa, 0xbb]);
+
+        let c = ApduCommand::parse(&[0xe0, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0xaa, 0x01, 0x00])
+            .unwrap();
+        assert_eq!(c.case(), ApduCase::Case4);
+        assert_eq!(c.le, Some(0x100));
+
+        assert_eq!(
+            ApduCommand::parse(&[0xe0, 0x02, 0x00, 0x00, 0x00, 0x01]).err(),
+            Some(StatusWords::BadLen)
+        );
+    }
+
+    #[test]
+    fn parse_legacy() {
+        let c =
+            ApduCommand::parse_legacy(&[0xe0, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0xaa]).unwrap();
+        assert_eq!(c.data, &[0xaa]);
+        assert_eq!(c.le, None);
+
+        let c = ApduCommand::parse_legacy(&[0xe0, 0x02, 0x00, 0x00, 0x01, 0xaa, 0xbb]).unwrap();
+        assert_eq!(c.data, &[0xaa]);
+    }
+}
@@ -0,0 +1,56 @@
+//! Deadlines counted in ticker events, used to wait for events with a timeout.
+
+/// Returned when a deadline expires before an event is received.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeoutError;
+
+/// Deadline expiring after a given number of [`Event::Ticker`](super::Event::Ticker) events.
+///
+/// A deadline can be shared by several calls to
+/// [`Comm::next_event_until`](super::Comm::next_event_until), for instance to bound the duration
+/// of a transaction spanning several APDUs.
+///
+/// # Examples
+///
+/// ```
+/// let mut deadline = Deadline::new(50);
+/// loop {
+///     match comm.next_event_until::<Instruction>(&mut deadline) {
+///         Ok(Event::Command(Instruction::SignChunk)) => { ... }
+///         Ok(_) => { ... }
+///         Err(TimeoutError) => break,
+///     }
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Deadline {
+    ticks: u32,
+}
+
+impl Deadline {
+    /// Creates a deadline expiring after `ticks` ticker events.
+    pub const fn new(ticks: u32) -> Self {
+        Deadline { ticks }
+    }
+
+    /// Returns the number of ticker events left before the deadline expires.
+    pub fn remaining(&self) -> u32 {
+        self.ticks
+    }
+
+    /// Returns true if the deadline has expired.
+    pub fn is_expired(&self) -> bool {
+        self.ticks == 0
+    }
+
+    /// Counts a ticker event, and returns true if the deadline has expired.
+    pub fn tick(&mut self) -> bool {
+        self.ticks = self.ticks.saturating_sub(1);
+        self.is_expired()
+    }
+
+    /// Restarts the deadline, to expire after `ticks` ticker events.
+    pub fn reset(&mut self, ticks: u32) {
+        self.ticks = ticks;
+    }
+}
@@ -0,0 +1,332 @@
+//! In-memory transport, used to test applications on the host with the `mock` feature.
+
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use super::{ConnectionEvent, Event, Transport};
+use core::convert::Infallible;
+#[cfg(not(any(target_os = "stax", target_os = "flex")))]
+use ledger_secure_sdk_sys::buttons::ButtonEvent;
+
+/// Event scripted in a [`MockTransport`].
+pub enum MockEvent {
+    /// Raw APDU command sent by the host
+    Command(Vec<u8>),
+    #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+    Button(ButtonEvent),
+    #[cfg(any(target_os = "stax", target_os = "flex"))]
+    TouchEvent,
+    Ticker,
+    Connection(ConnectionEvent),
+}
+
+/// Transport replaying scripted events and capturing responses, used by default by
+/// [`Comm`](super::Comm) with the `mock` feature.
+///
+/// Events are returned by [`Comm::next_event`](super::Comm::next_event) in the order they have
+/// been scripted, and responses are captured with their status word. Waiting for an event once
+/// all the scripted events have been consumed panics, as no more events could ever be received.
+///
+/// The device is unlocked unless set otherwise with [`MockTransport::set_locked`], and asking
+/// the user to validate the PIN unlocks it if set with [`MockTransport::set_pin_accepted`].
+///
+/// # Examples
+///
+/// ```
+/// let mut comm = Comm::new();
+/// comm.transport.push_command(&[0xe0, 0x01, 0x00, 0x00]);
+///
+/// if let Event::Command(ins) = comm.next_event::<Instruction>() {
+///     handle_apdu(&mut comm, ins);
+/// }
+/// assert_eq!(comm.transport.pop_response().unwrap(), [0x01, 0x02, 0x90, 0x00]);
+/// ```
+pub struct MockTransport {
+    events: VecDeque<MockEvent>,
+    current: Option<MockEvent>,
+    received: Option<usize>,
+    responses: VecDeque<Vec<u8>>,
+    locked: bool,
+    pin_accepted: bool,
+    pin_requests: usize,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockTransport {
+    pub const fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            current: None,
+            received: None,
+            responses: VecDeque::new(),
+            locked: false,
+            pin_accepted: false,
+            pin_requests: 0,
+        }
+    }
+
+    /// Sets whether the device is locked.
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    /// Sets whether the user validates the PIN when asked to, which unlocks the device.
+    pub fn set_pin_accepted(&mut self, accepted: bool) {
+        self.pin_accepted = accepted;
+    }
+
+    /// Returns the number of times the user has been asked to validate the PIN.
+    pub fn pin_requests(&self) -> usize {
+        self.pin_requests
+    }
+
+    /// Scripts the reception of a raw APDU command.
+    pub fn push_command(&mut self, apdu: &[u8]) {
+        self.events.push_back(MockEvent::Command(apdu.to_vec()));
+    }
+
+    /// Scripts a button event.
+    #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+    pub fn push_button(&mut self, event: ButtonEvent) {
+        self.events.push_back(MockEvent::Button(event));
+    }
+
+    /// Scripts a touch event.
+    #[cfg(any(target_os = "stax", target_os = "flex"))]
+    pub fn push_touch(&mut self) {
+        self.events.push_back(MockEvent::TouchEvent);
+    }
+
+    /// Scripts a ticker event.
+    pub fn push_ticker(&mut self) {
+        self.events.push_back(MockEvent::Ticker);
+    }
+
+    /// Scripts a connection or power state change.
+    pub fn push_connection(&mut self, event: ConnectionEvent) {
+        self.events.push_back(MockEvent::Connection(event));
+    }
+
+    /// Returns the number of scripted events which have not been received yet.
+    pub fn pending_events(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Removes and returns the oldest captured response, including its status word.
+    pub fn pop_response(&mut self) -> Option<Vec<u8>> {
+        self.responses.pop_front()
+    }
+}
+
+impl Transport for MockTransport {
+    fn recv(&mut self, _packet: &mut [u8; 128]) {
+        match self.events.pop_front() {
+            Some(event) => self.current = Some(event),
+            None => panic!("no more scripted events"),
+        }
+    }
+
+    fn process(
+        &mut self,
+        _packet: &mut [u8; 128],
+        apdu_buffer: &mut [u8],
+    ) -> Option<Event<Infallible>> {
+        match self.current.take()? {
+            MockEvent::Command(apdu) => {
+                apdu_buffer[..apdu.len()].copy_from_slice(&apdu);
+                self.received = Some(apdu.len());
+                None
+            }
+            #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+            MockEvent::Button(event) => Some(Event::Button(event)),
+            #[cfg(any(target_os = "stax", target_os = "flex"))]
+            MockEvent::TouchEvent => Some(Event::TouchEvent),
+            MockEvent::Ticker => Some(Event::Ticker),
+            MockEvent::Connection(event) => Some(Event::Connection(event)),
+        }
+    }
+
+    fn received(&self) -> Option<usize> {
+        self.received
+    }
+
+    fn send(&mut self, apdu_buffer: &mut [u8], len: usize) {
+        self.responses.push_back(apdu_buffer[..len].to_vec());
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.received = None;
+    }
+
+    fn is_device_locked(&self) -> bool {
+        self.locked
+    }
+
+    fn validate_pin(&mut self) {
+        self.pin_requests += 1;
+        if self.pin_accepted {
+            self.locked = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io::{ApduHeader, Comm, Deadline, LockPolicy, Reply, StatusWords, TimeoutError};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn scripted_events() {
+        let mut comm = Comm::new().set_expected_cla(0xe0);
+        comm.transport
+            .push_command(&[0xe0, 0x02, 0x00, 0x00, 0x01, 0xaa]);
+        comm.transport.push_command(&[0xb0, 0x02, 0x00, 0x00]);
+        comm.transport.push_ticker();
+
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Command(h) if h.ins == 0x02), true);
+        assert_eq!(comm.get_data(), Ok(&[0xaa][..]));
+        comm.append(&[0x01, 0x02]);
+        comm.reply_ok();
+        let response = comm.transport.pop_response();
+        assert_eq!(response.as_deref(), Some(&[0x01, 0x02, 0x90, 0x00][..]));
+
+        // Rejected commands are answered without being surfaced
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+        assert_eq!(comm.transport.pending_events(), 0);
+        let sw = (StatusWords::BadCla as u16).to_be_bytes();
+        assert_eq!(comm.transport.pop_response().as_deref(), Some(&sw[..]));
+    }
+
+    #[test]
+    fn bolos_handler() {
+        fn handler(comm: &mut Comm, header: ApduHeader) -> Option<Result<(), Reply>> {
+            match header.ins {
+                0x10 => {
+                    comm.append(&[0x01]);
+                    Some(Ok(()))
+                }
+                0x11 => Some(Err(StatusWords::BadP1P2.into())),
+                _ => None,
+            }
+        }
+
+        let mut comm = Comm::new()
+            .set_expected_cla(0xe0)
+            .set_bolos_handler(handler);
+        comm.transport.push_command(&[0xb0, 0x10, 0x00, 0x00]);
+        comm.transport.push_command(&[0xb0, 0x11, 0x00, 0x00]);
+        comm.transport.push_ticker();
+
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+        let response = comm.transport.pop_response();
+        assert_eq!(response.as_deref(), Some(&[0x01, 0x90, 0x00][..]));
+        let response = comm.transport.pop_response();
+        assert_eq!(response.as_deref(), Some(&[0x6e, 0x02][..]));
+    }
+
+    #[test]
+    fn connection_events() {
+        let mut comm = Comm::new();
+        comm.transport.push_connection(ConnectionEvent::UsbSuspend);
+        comm.transport.push_ticker();
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+
+        let mut comm = Comm::new().set_connection_events(true);
+        comm.transport
+            .push_connection(ConnectionEvent::BleDisconnected);
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(
+            matches!(event, Event::Connection(ConnectionEvent::BleDisconnected)),
+            true
+        );
+    }
+
+    #[test]
+    fn event_timeout() {
+        let mut comm = Comm::new();
+        comm.transport.push_ticker();
+        comm.transport.push_ticker();
+        comm.transport.push_ticker();
+        comm.transport.push_command(&[0xe0, 0x02, 0x00, 0x00]);
+
+        let event = comm.next_event_timeout::<ApduHeader>(2);
+        assert_eq!(event.err(), Some(TimeoutError));
+        assert_eq!(comm.transport.pending_events(), 2);
+
+        let mut deadline = Deadline::new(2);
+        let event = comm.next_event_until::<ApduHeader>(&mut deadline);
+        assert_eq!(
+            matches!(event, Ok(Event::Command(h)) if h.ins == 0x02),
+            true
+        );
+        assert_eq!(deadline.remaining(), 1);
+
+        let event = comm.next_event_timeout::<ApduHeader>(0);
+        assert_eq!(event.err(), Some(TimeoutError));
+    }
+
+    #[test]
+    fn lock_policy_reject() {
+        // Commands are processed by default
+        let mut comm = Comm::new();
+        comm.transport.set_locked(true);
+        comm.transport.push_command(&[0xe0, 0x02, 0x00, 0x00]);
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Command(h) if h.ins == 0x02), true);
+
+        let mut comm = Comm::new().set_lock_policy(LockPolicy::Reject);
+        comm.transport.set_locked(true);
+        comm.transport.set_pin_accepted(true);
+        comm.transport.push_command(&[0xe0, 0x02, 0x00, 0x00]);
+        comm.transport.push_ticker();
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+        let sw = (StatusWords::DeviceLocked as u16).to_be_bytes();
+        assert_eq!(comm.transport.pop_response().as_deref(), Some(&sw[..]));
+        assert_eq!(comm.transport.pin_requests(), 0);
+
+        comm.transport.set_locked(false);
+        comm.transport.push_command(&[0xe0, 0x02, 0x00, 0x00]);
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Command(h) if h.ins == 0x02), true);
+    }
+
+    #[test]
+    fn lock_policy_validate_pin() {
+        let mut comm = Comm::new().set_lock_policy(LockPolicy::ValidatePin);
+        comm.transport.set_locked(true);
+        comm.transport.push_command(&[0xe0, 0x02, 0x00, 0x00]);
+        comm.transport.push_ticker();
+
+        // PIN not validated
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+        let sw = (StatusWords::DeviceLocked as u16).to_be_bytes();
+        assert_eq!(comm.transport.pop_response().as_deref(), Some(&sw[..]));
+        assert_eq!(comm.transport.pin_requests(), 1);
+
+        // PIN validated, then the device stays unlocked
+        comm.transport.set_pin_accepted(true);
+        comm.transport.push_command(&[0xe0, 0x02, 0x00, 0x00]);
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Command(h) if h.ins == 0x02), true);
+        comm.reply_ok();
+        comm.transport.push_command(&[0xe0, 0x03, 0x00, 0x00]);
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Command(h) if h.ins == 0x03), true);
+        assert_eq!(comm.transport.pin_requests(), 2);
+    }
+}
@@ -0,0 +1,205 @@
+//! Bounds-checked cursor for parsing command data.
+
+use super::StatusWords;
+
+/// Cursor over the data of a command, with bounds-checked read methods.
+///
+/// Every read method returns [`StatusWords::BadLen`] if the data is too short, and leaves the
+/// cursor unchanged in that case. This allows malformed input to be rejected with the `?`
+/// operator instead of panicking.
+///
+/// # Examples
+///
+/// ```
+/// fn handle_sign(comm: &mut Comm) -> Result<(), StatusWords> {
+///     let mut reader = comm.reader()?;
+///     let mut path = [0u32; 10];
+///     let path = reader.read_bip32_path(&mut path)?;
+///     let amount = reader.read_u64_be()?;
+///     let memo = reader.read_length_prefixed()?;
+///     reader.finish()?;
+///     ...
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ApduReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ApduReader<'a> {
+    /// Creates a reader starting at the beginning of `data`.
+    pub const fn new(data: &'a [u8]) -> Self {
+        ApduReader { data, offset: 0 }
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    /// Returns true if all the data has been read.
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Returns the data left to read, without consuming it.
+    pub fn peek_remaining(&self) -> &'a [u8] {
+        &self.data[self.offset..]
+    }
+
+    /// Returns an error if some data has not been read.
+    pub fn finish(&self) -> Result<(), StatusWords> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(StatusWords::BadLen)
+        }
+    }
+
+    /// Reads the next `n` bytes.
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], StatusWords> {
+        if n > self.remaining() {
+            return Err(StatusWords::BadLen);
+        }
+        let bytes = &self.data[self.offset..self.offset + n];
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    /// Reads the next `N` bytes into an array.
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], StatusWords> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    /// Reads all the remaining bytes.
+    pub fn read_remaining(&mut self) -> &'a [u8] {
+        let bytes = self.peek_remaining();
+        self.offset = self.data.len();
+        bytes
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StatusWords> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16_be(&mut self) -> Result<u16, StatusWords> {
+        self.read_array().map(u16::from_be_bytes)
+    }
+
+    pub fn read_u16_le(&mut self) -> Result<u16, StatusWords> {
+        self.read_array().map(u16::from_le_bytes)
+    }
+
+    pub fn read_u32_be(&mut self) -> Result<u32, StatusWords> {
+        self.read_array().map(u32::from_be_bytes)
+    }
+
+    pub fn read_u32_le(&mut self) -> Result<u32, StatusWords> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    pub fn read_u64_be(&mut self) -> Result<u64, StatusWords> {
+        self.read_array().map(u64::from_be_bytes)
+    }
+
+    pub fn read_u64_le(&mut self) -> Result<u64, StatusWords> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    /// Reads a variable length integer, encoded as a Bitcoin `CompactSize`: values below `0xfd`
+    /// are encoded on one byte, larger values are prefixed with `0xfd`, `0xfe` or `0xff` and
+    /// encoded as little-endian `u16`, `u32` or `u64` respectively.
+    pub fn read_varint(&mut self) -> Result<u64, StatusWords> {
+        let start = self.offset;
+        let res = match self.read_u8()? {
+            0xfd => self.read_u16_le().map(u64::from),
+            0xfe => self.read_u32_le().map(u64::from),
+            0xff => self.read_u64_le(),
+            n => Ok(u64::from(n)),
+        };
+        if res.is_err() {
+            self.offset = start;
+        }
+        res
+    }
+
+    /// Reads data prefixed with its length, encoded on one byte.
+    pub fn read_length_prefixed(&mut self) -> Result<&'a [u8], StatusWords> {
+        let start = self.offset;
+        let len = self.read_u8()? as usize;
+        self.read_bytes(len).inspect_err(|_| self.offset = start)
+    }
+
+    /// Reads a BIP32 derivation path, encoded as the number of components on one byte followed
+    /// by the components as big-endian `u32`, into `path`.
+    ///
+    /// Returns the slice of `path` holding the components, or [`StatusWords::BadLen`] if the
+    /// path has more components than `path` can hold.
+    pub fn read_bip32_path<'p>(&mut self, path: &'p mut [u32]) -> Result<&'p [u32], StatusWords> {
+        let start = self.offset;
+        let len = self.read_u8()? as usize;
+        if len > path.len() || len * 4 > self.remaining() {
+            self.offset = start;
+            return Err(StatusWords::BadLen);
+        }
+        for component in path[..len].iter_mut() {
+            *component = self.read_u32_be()?;
+        }
+        Ok(&path[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn read_integers() {
+        let mut r = ApduReader::new(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]);
+        assert_eq!(r.read_u8(), Ok(0x01));
+        assert_eq!(r.read_u16_be(), Ok(0x0203));
+        assert_eq!(r.read_u32_le(), Ok(0x07060504));
+        assert_eq!(r.read_u8(), Err(StatusWords::BadLen));
+        assert_eq!(r.finish(), Ok(()));
+    }
+
+    #[test]
+    fn read_varint() {
+        let mut r = ApduReader::new(&[0xfc, 0xfd, 0x34, 0x12, 0xfe, 0x01]);
+        assert_eq!(r.read_varint(), Ok(0xfc));
+        assert_eq!(r.read_varint(), Ok(0x1234));
+        assert_eq!(r.read_varint(), Err(StatusWords::BadLen));
+        assert_eq!(r.remaining(), 2);
+    }
+
+    #[test]
+    fn read_bip32_path() {
+        let data = [
+            0x02, 0x80, 0x00, 0x00, 0x2c, 0x80, 0x00, 0x00, 0x00, 0x02, 0xaa, 0xbb,
+        ];
+        let mut r = ApduReader::new(&data);
+        let mut path = [0u32; 2];
+        assert_eq!(
+            r.read_bip32_path(&mut path),
+            Ok(&[0x8000002c, 0x80000000][..])
+        );
+        assert_eq!(r.read_length_prefixed(), Ok(&[0xaa, 0xbb][..]));
+        assert_eq!(r.is_empty(), true);
+
+        let mut r = ApduReader::new(&data);
+        let mut path = [0u32; 1];
+        assert_eq!(r.read_bip32_path(&mut path), Err(StatusWords::BadLen));
+        assert_eq!(r.position(), 0);
+    }
+}
@@ -0,0 +1,673 @@
+//! Encrypted and authenticated channel between the host and the device.
+
+use super::{Reply, StatusWords, SyscallError};
+use crate::ecc::{ECPrivateKey, Secp256r1};
+use crate::hmac::{sha2::Sha2_256, HMACInit};
+use crate::random::rand_bytes;
+use ledger_secure_sdk_sys::{
+    cx_chachapoly_context_t, cx_chachapoly_decrypt_and_auth, cx_chachapoly_encrypt_and_tag,
+    cx_chachapoly_init, cx_chachapoly_set_key, CX_OK,
+};
+use zeroize::Zeroize;
+
+/// Length of the keys and of the ECDH shared secrets.
+const KEY_LEN: usize = 32;
+/// Length of an uncompressed P-256 point.
+const POINT_LEN: usize = 65;
+/// Length of the counter prefixing wrapped commands.
+const COUNTER_LEN: usize = 4;
+/// Length of the ChaCha20-Poly1305 nonces.
+const NONCE_LEN: usize = 12;
+/// Offset of the data in a short APDU.
+const DATA_OFFSET: usize = 5;
+
+/// Encrypted and authenticated channel, through which APDUs are exchanged with a host knowing the
+/// public key of the device identity key.
+///
+/// Once enabled with [`Comm::set_secure_channel`](super::Comm::set_secure_channel), commands
+/// using the CLA byte of the channel are handled by [`Comm`](super::Comm) before being surfaced:
+///
+/// * `INS_OPEN` (P1 = P2 = 0) opens a new session. Its data is an ephemeral P-256 public key of
+///   the host (uncompressed, 65 bytes), and the device replies with its own ephemeral public key.
+///   Session keys are derived from the ECDH secrets of the host key with both the identity key and
+///   the ephemeral key of the device, so only the owner of the identity key can answer.
+/// * `INS_WRAPPED` (P1 = P2 = 0) carries an encrypted command. Its data is a big-endian 32-bit
+///   counter, followed by the encrypted inner APDU (CLA, INS, P1, P2, optional Lc and data) and a
+///   16-byte tag. The inner APDU is decrypted in place, and [`Event::Command`](super::Event) is
+///   produced from it as if it had been received in plaintext.
+///
+/// The response to a wrapped command (data and status word) is encrypted and followed by a
+/// 16-byte tag, and transmitted with status word `0x9000`. With response chaining, the next parts
+/// of the response must be retrieved with wrapped GET RESPONSE commands.
+///
+/// Messages are encrypted with ChaCha20-Poly1305 (RFC 8439), without associated data, with one key
+/// per direction and a nonce made of 8 zero bytes followed by the counter. The keys are derived
+/// with HKDF-SHA256 (RFC 5869) from the input keying material `identity secret || ephemeral
+/// secret`, with the salt `host ephemeral public key || device ephemeral public key` and the infos
+/// `"cmd key"` and `"rsp key"`.
+///
+/// Counters of wrapped commands must be strictly increasing within a session, the response using
+/// the counter of its command. Replayed commands are rejected with
+/// [`SyscallError::InvalidCounter`], commands which fail authentication, or are received before
+/// a session is opened, with [`SyscallError::Security`].
+///
+/// Only the device is authenticated: any host can open a session, which replaces the session in
+/// progress once established. The channel protects the commands and responses of a session from
+/// being read or modified in transit, but does not restrict which host sends commands, even when
+/// required. Applications which must restrict it have to authenticate the host themselves, for
+/// instance by verifying a signature of its ephemeral key, or by asking the user to confirm the
+/// pairing.
+///
+/// # Examples
+///
+/// ```
+/// let identity = Secp256r1::derive_from_path(&IDENTITY_PATH);
+/// let channel = SecureChannel::new(0xe1, identity).set_required(true);
+/// let mut comm = Comm::new().set_expected_cla(0xe0).set_secure_channel(channel);
+/// ```
+pub struct SecureChannel {
+    cla: u8,
+    identity: ECPrivateKey<KEY_LEN, 'W'>,
+    required: bool,
+    keys: Option<SessionKeys>,
+    /// Counter of the last wrapped command.
+    counter: u32,
+    /// Set from the reception of a wrapped command until its response is transmitted.
+    wrapping: bool,
+}
+
+/// Outcome of the processing of an incoming APDU by the secure channel.
+pub(crate) enum Incoming {
+    /// Plaintext APDU, processed as usual.
+    Plain,
+    /// Wrapped APDU, decrypted in place, with the length of the inner APDU.
+    Unwrapped(usize),
+    /// APDU handled by the channel, with the length of the response data and the status word.
+    Handled(usize, Reply),
+}
+
+impl SecureChannel {
+    /// INS byte of the command opening a session.
+    pub const INS_OPEN: u8 = 0x01;
+    /// INS byte of the commands carrying an encrypted APDU.
+    pub const INS_WRAPPED: u8 = 0x02;
+    /// Length of the authentication tags.
+    pub const TAG_LEN: usize = 16;
+    /// Number of bytes added to a response by the encryption, status word of the inner response
+    /// included.
+    pub const OVERHEAD: usize = 2 + Self::TAG_LEN;
+
+    /// Creates a secure channel using commands with CLA byte `cla`, which must differ from the
+    /// CLA of the application, and authenticated by the P-256 key `identity`.
+    pub fn new(cla: u8, identity: ECPrivateKey<KEY_LEN, 'W'>) -> Self {
+        SecureChannel {
+            cla,
+            identity,
+            required: false,
+            keys: None,
+            counter: 0,
+            wrapping: false,
+        }
+    }
+
+    /// Defines whether plaintext commands are rejected with [`SyscallError::Security`].
+    ///
+    /// BOLOS commands (CLA `0xb0`) are always accepted in plaintext. As the host is not
+    /// authenticated, this does not prevent another host from opening its own session.
+    pub fn set_required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Returns true if a session is open.
+    pub fn is_open(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Closes the session in progress, if any.
+    pub fn close(&mut self) {
+        self.keys = None;
+        self.counter = 0;
+        self.wrapping = false;
+    }
+
+    /// Returns true if the response being built must be encrypted.
+    pub(crate) fn is_wrapping(&self) -> bool {
+        self.wrapping
+    }
+
+    /// Processes the APDU of length `rx` held in `buffer`.
+    pub(crate) fn filter(&mut self, buffer: &mut [u8], rx: usize) -> Incoming {
+        self.wrapping = false;
+        if buffer[0] != self.cla {
+            if self.required && buffer[0] != 0xb0 {
+                return Incoming::Handled(0, SyscallError::Security.into());
+            }
+            return Incoming::Plain;
+        }
+        if rx < DATA_OFFSET || buffer[4] as usize != rx - DATA_OFFSET {
+            return Incoming::Handled(0, StatusWords::BadLen.into());
+        }
+        if buffer[2] != 0 || buffer[3] != 0 {
+            return Incoming::Handled(0, StatusWords::BadP1P2.into());
+        }
+        match buffer[1] {
+            Self::INS_OPEN => match self.open(buffer, rx) {
+                Ok(len) => Incoming::Handled(len, StatusWords::Ok.into()),
+                Err(e) => Incoming::Handled(0, e),
+            },
+            Self::INS_WRAPPED => match self.unwrap(buffer, rx) {
+                Ok(len) => Incoming::Unwrapped(len),
+                Err(e) => Incoming::Handled(0, e),
+            },
+            _ => Incoming::Handled(0, StatusWords::BadIns.into()),
+        }
+    }
+
+    /// Encrypts the response data of length `tx` held in `buffer`, followed by the status word
+    /// `sw`, and returns the length of the encrypted response.
+    ///
+    /// Data which does not fit in `buffer` once encrypted is replaced by
+    /// [`SyscallError::Overflow`].
+    pub(crate) fn wrap(&mut self, buffer: &mut [u8], tx: usize, sw: u16) -> Result<usize, Reply> {
+        self.wrapping = false;
+        let keys = self.keys.as_ref().ok_or(SyscallError::InvalidState)?;
+        let (tx, sw) = if tx + Self::OVERHEAD > buffer.len() - 2 {
+            (0, Reply::from(SyscallError::Overflow).0)
+        } else {
+            (tx, sw)
+        };
+        buffer[tx..tx + 2].copy_from_slice(&sw.to_be_bytes());
+        let (body, rest) = buffer.split_at_mut(tx + 2);
+        let tag = Aead::new(&keys.rsp)?.encrypt(self.counter, body)?;
+        rest[..Self::TAG_LEN].copy_from_slice(&tag);
+        Ok(body.len() + Self::TAG_LEN)
+    }
+
+    /// Opens a new session from the host ephemeral key held in `buffer`, and writes the device
+    /// ephemeral key in its place.
+    ///
+    /// The session in progress is only replaced once the new one has been established.
+    fn open(&mut self, buffer: &mut [u8], rx: usize) -> Result<usize, Reply> {
+        if rx - DATA_OFFSET != POINT_LEN {
+            return Err(StatusWords::BadLen.into());
+        }
+        let host = &buffer[DATA_OFFSET..rx];
+
+        let mut seed = [0u8; KEY_LEN];
+        rand_bytes(&mut seed);
+        let ephemeral = Secp256r1::from(&seed);
+        seed.zeroize();
+        let ephemeral_pk = ephemeral
+            .public_key()
+            .map_err(|_| SyscallError::InvalidState)?;
+
+        let mut identity_secret = self
+            .identity
+            .ecdh(host)
+            .map_err(|_| SyscallError::InvalidParameter)?;
+        let mut ephemeral_secret = ephemeral
+            .ecdh(host)
+            .map_err(|_| SyscallError::InvalidParameter)?;
+        // Bind the keys to both ephemeral keys of the handshake
+        let mut salt = [0u8; 2 * POINT_LEN];
+        salt[..POINT_LEN].copy_from_slice(host);
+        salt[POINT_LEN..].copy_from_slice(&ephemeral_pk.pubkey);
+        let keys = SessionKeys::derive(&identity_secret, &ephemeral_secret, &salt);
+        identity_secret.zeroize();
+        ephemeral_secret.zeroize();
+
+        let keys = keys?;
+        self.close();
+        self.keys = Some(keys);
+        buffer[..POINT_LEN].copy_from_slice(&ephemeral_pk.pubkey);
+        Ok(POINT_LEN)
+    }
+
+    /// Authenticates and decrypts the wrapped APDU held in `buffer`, and moves the inner APDU to
+    /// the start of `buffer`.
+    fn unwrap(&mut self, buffer: &mut [u8], rx: usize) -> Result<usize, Reply> {
+        let keys = self.keys.as_ref().ok_or(SyscallError::Security)?;
+        let start = DATA_OFFSET + COUNTER_LEN;
+        if rx < start + 4 + Self::TAG_LEN {
+            return Err(StatusWords::BadLen.into());
+        }
+        let mut counter = [0u8; COUNTER_LEN];
+        counter.copy_from_slice(&buffer[DATA_OFFSET..start]);
+        let counter = u32::from_be_bytes(counter);
+        if counter <= self.counter {
+            return Err(SyscallError::InvalidCounter.into());
+        }
+
+        let (body, tag) = buffer[start..rx].split_at_mut(rx - start - Self::TAG_LEN);
+        Aead::new(&keys.cmd)?.decrypt(counter, body, tag)?;
+
+        let len = body.len();
+        buffer.copy_within(start..start + len, 0);
+        self.counter = counter;
+        self.wrapping = true;
+        Ok(len)
+    }
+}
+
+/// Keys of a session, one per direction.
+struct SessionKeys {
+    cmd: [u8; KEY_LEN],
+    rsp: [u8; KEY_LEN],
+}
+
+/// Cleanup keys from memory when closing the session.
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        self.cmd.zeroize();
+        self.rsp.zeroize();
+    }
+}
+
+impl SessionKeys {
+    fn derive(identity_secret: &[u8], ephemeral_secret: &[u8], salt: &[u8]) -> Result<Self, Reply> {
+        let mut prk = hkdf_extract(salt, &[identity_secret, ephemeral_secret])?;
+        let cmd = hkdf_expand(&prk, b"cmd key");
+        let rsp = hkdf_expand(&prk, b"rsp key");
+        prk.zeroize();
+        Ok(SessionKeys {
+            cmd: cmd?,
+            rsp: rsp?,
+        })
+    }
+}
+
+/// HKDF-Extract with SHA-256 (RFC 5869), of the concatenation of `ikm`.
+fn hkdf_extract(salt: &[u8], ikm: &[&[u8]]) -> Result<[u8; KEY_LEN], Reply> {
+    let mut prk = [0u8; KEY_LEN];
+    let mut mac = Sha2_256::new(salt);
+    for part in ikm {
+        mac.update(part)?;
+    }
+    mac.finalize(&mut prk)?;
+    Ok(prk)
+}
+
+/// HKDF-Expand with SHA-256 (RFC 5869), of an output as long as the hash: a single block.
+fn hkdf_expand(prk: &[u8], info: &[u8]) -> Result<[u8; KEY_LEN], Reply> {
+    let mut okm = [0u8; KEY_LEN];
+    let mut mac = Sha2_256::new(prk);
+    mac.update(info)?;
+    mac.update(&[0x01])?;
+    mac.finalize(&mut okm)?;
+    Ok(okm)
+}
+
+/// ChaCha20-Poly1305 context, initialized with a key.
+struct Aead {
+    ctx: cx_chachapoly_context_t,
+}
+
+/// Cleanup the key schedule from memory.
+impl Drop for Aead {
+    fn drop(&mut self) {
+        let ctx = &mut self.ctx as *mut cx_chachapoly_context_t as *mut u8;
+        unsafe {
+            core::slice::from_raw_parts_mut(ctx, core::mem::size_of::<cx_chachapoly_context_t>())
+        }
+        .zeroize();
+    }
+}
+
+impl Aead {
+    fn new(key: &[u8; KEY_LEN]) -> Result<Self, Reply> {
+        let mut aead = Aead {
+            ctx: Default::default(),
+        };
+        unsafe { cx_chachapoly_init(&mut aead.ctx) };
+        let err = unsafe { cx_chachapoly_set_key(&mut aead.ctx, key.as_ptr(), key.len()) };
+        if err != CX_OK {
+            return Err(SyscallError::Unspecified.into());
+        }
+        Ok(aead)
+    }
+
+    /// Returns the nonce of the messages of `counter`.
+    fn nonce(counter: u32) -> [u8; NONCE_LEN] {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[NONCE_LEN - COUNTER_LEN..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    /// Encrypts `data` of `counter` in place, and returns its tag.
+    fn encrypt(
+        &mut self,
+        counter: u32,
+        data: &mut [u8],
+    ) -> Result<[u8; SecureChannel::TAG_LEN], Reply> {
+        let nonce = Self::nonce(counter);
+        let mut tag = [0u8; SecureChannel::TAG_LEN];
+        let data_ptr = data.as_mut_ptr();
+        let err = unsafe {
+            cx_chachapoly_encrypt_and_tag(
+                &mut self.ctx,
+                data_ptr,
+                data.len(),
+                nonce.as_ptr(),
+                nonce.len(),
+                core::ptr::null(),
+                0,
+                data_ptr,
+                tag.as_mut_ptr(),
+            )
+        };
+        if err != CX_OK {
+            return Err(SyscallError::Unspecified.into());
+        }
+        Ok(tag)
+    }
+
+    /// Authenticates with `tag` and decrypts `data` of `counter` in place.
+    fn decrypt(&mut self, counter: u32, data: &mut [u8], tag: &[u8]) -> Result<(), Reply> {
+        if tag.len() != SecureChannel::TAG_LEN {
+            return Err(SyscallError::Security.into());
+        }
+        let nonce = Self::nonce(counter);
+        let data_ptr = data.as_mut_ptr();
+        let err = unsafe {
+            cx_chachapoly_decrypt_and_auth(
+                &mut self.ctx,
+                data_ptr,
+                data.len(),
+                nonce.as_ptr(),
+                nonce.len(),
+                core::ptr::null(),
+                0,
+                data_ptr,
+                tag.as_ptr(),
+            )
+        };
+        if err != CX_OK {
+            return Err(SyscallError::Security.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io::{ApduHeader, Comm, Event, Transport};
+    use crate::testing::TestType;
+    use core::convert::Infallible;
+    use testmacro::test_item as test;
+
+    const IDENTITY: [u8; 32] = [0x42; 32];
+    const HOST: [u8; 32] = [0x17; 32];
+
+    /// Wraps `apdu` as the host would, in a wrapped command held in `buffer`.
+    fn host_wrap(
+        keys: &SessionKeys,
+        counter: u32,
+        apdu: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<usize, Reply> {
+        let len = COUNTER_LEN + apdu.len() + SecureChannel::TAG_LEN;
+        buffer[..DATA_OFFSET].copy_from_slice(&[0xe1, 0x02, 0x00, 0x00, len as u8]);
+        buffer[DATA_OFFSET..DATA_OFFSET + COUNTER_LEN].copy_from_slice(&counter.to_be_bytes());
+        let body = &mut buffer[DATA_OFFSET + COUNTER_LEN..DATA_OFFSET + COUNTER_LEN + apdu.len()];
+        body.copy_from_slice(apdu);
+        let tag = Aead::new(&keys.cmd)?.encrypt(counter, body)?;
+        let end = DATA_OFFSET + len;
+        buffer[end - SecureChannel::TAG_LEN..end].copy_from_slice(&tag);
+        Ok(end)
+    }
+
+    /// Derives the session keys as the host would, from the device ephemeral key `device`.
+    fn host_keys(
+        host: &ECPrivateKey<KEY_LEN, 'W'>,
+        identity: &[u8],
+        device: &[u8],
+    ) -> Result<SessionKeys, ()> {
+        let host_pk = host.public_key().map_err(|_| ())?;
+        let identity_secret = host.ecdh(identity).map_err(|_| ())?;
+        let ephemeral_secret = host.ecdh(device).map_err(|_| ())?;
+        let mut salt = [0u8; 2 * POINT_LEN];
+        salt[..POINT_LEN].copy_from_slice(&host_pk.pubkey);
+        salt[POINT_LEN..].copy_from_slice(device);
+        SessionKeys::derive(&identity_secret, &ephemeral_secret, &salt).map_err(|_| ())
+    }
+
+    /// Transport keeping the last response, commands being written directly in the [`Comm`].
+    struct CaptureTransport {
+        response: [u8; 260],
+        len: usize,
+    }
+
+    impl Transport for CaptureTransport {
+        fn recv(&mut self, _packet: &mut [u8; 128]) {
+            unreachable!();
+        }
+
+        fn process(
+            &mut self,
+            _packet: &mut [u8; 128],
+            _apdu_buffer: &mut [u8],
+        ) -> Option<Event<Infallible>> {
+            None
+        }
+
+        fn received(&self) -> Option<usize> {
+            None
+        }
+
+        fn send(&mut self, apdu_buffer: &mut [u8], len: usize) {
+            self.response[..len].copy_from_slice(&apdu_buffer[..len]);
+            self.len = len;
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    /// Processes `apdu` as if it had been received by `comm`.
+    fn receive(comm: &mut Comm<CaptureTransport>, apdu: &[u8]) -> Option<Event<ApduHeader>> {
+        comm.apdu_buffer[..apdu.len()].copy_from_slice(apdu);
+        comm.rx = apdu.len();
+        comm.event_pending = true;
+        comm.check_event()
+    }
+
+    /// Decrypts the last response captured by `comm`, and returns its length.
+    fn host_unwrap(
+        keys: &SessionKeys,
+        counter: u32,
+        comm: &mut Comm<CaptureTransport>,
+    ) -> Result<usize, ()> {
+        let response = &mut comm.transport.response[..comm.transport.len];
+        let (body, sw) = response.split_at_mut(response.len() - 2);
+        assert_eq!(sw, &[0x90, 0x00]);
+        let (body, tag) = body.split_at_mut(body.len() - SecureChannel::TAG_LEN);
+        Aead::new(&keys.rsp)
+            .and_then(|mut aead| aead.decrypt(counter, body, tag))
+            .map_err(|_| ())?;
+        Ok(body.len())
+    }
+
+    #[test]
+    fn secure_channel_response_chaining() {
+        static mut RESPONSE_BUFFER: [u8; 512] = [0u8; 512];
+        let identity = Secp256r1::from(&IDENTITY);
+        let identity_pk = identity.public_key().map_err(|_| ())?;
+        let mut comm = Comm::with_transport(CaptureTransport {
+            response: [0; 260],
+            len: 0,
+        })
+        .set_response_buffer(unsafe { &mut *core::ptr::addr_of_mut!(RESPONSE_BUFFER) })
+        .set_secure_channel(SecureChannel::new(0xe1, identity));
+
+        let host = Secp256r1::from(&HOST);
+        let host_pk = host.public_key().map_err(|_| ())?;
+        let mut open = [0u8; DATA_OFFSET + POINT_LEN];
+        open[..DATA_OFFSET].copy_from_slice(&[0xe1, 0x01, 0x00, 0x00, POINT_LEN as u8]);
+        open[DATA_OFFSET..].copy_from_slice(&host_pk.pubkey);
+        assert_eq!(receive(&mut comm, &open).is_none(), true);
+        let mut device_pk = [0u8; POINT_LEN];
+        device_pk.copy_from_slice(&comm.transport.response[..POINT_LEN]);
+        let keys = host_keys(&host, &identity_pk.pubkey, &device_pk)?;
+
+        let command = [0xe0, 0x03, 0x00, 0x00];
+        let get_response = [0x00, 0xc0, 0x00, 0x00, 0x00];
+        let mut buffer = [0u8; 260];
+        let capacity = 258 - SecureChannel::OVERHEAD;
+
+        // Wrapped command with a response larger than the APDU buffer
+        let rx = host_wrap(&keys, 1, &command, &mut buffer).map_err(|_| ())?;
+        let event = receive(&mut comm, &buffer[..rx]);
+        assert_eq!(
+            matches!(event, Some(Event::Command(h)) if h.ins == 0x03),
+            true
+        );
+        comm.append(&[0xaa; 300]);
+        comm.reply_ok();
+        let len = host_unwrap(&keys, 1, &mut comm)?;
+        assert_eq!(len, capacity + 2);
+        let remaining = (300 - capacity) as u8;
+        assert_eq!(comm.transport.response[capacity..len], [0x61, remaining]);
+
+        // The rest is served to a wrapped GET RESPONSE
+        let rx = host_wrap(&keys, 2, &get_response, &mut buffer).map_err(|_| ())?;
+        assert_eq!(receive(&mut comm, &buffer[..rx]).is_none(), true);
+        let len = host_unwrap(&keys, 2, &mut comm)?;
+        assert_eq!(len, remaining as usize + 2);
+        assert_eq!(comm.transport.response[len - 2..len], [0x90, 0x00]);
+
+        // A plaintext GET RESPONSE discards it
+        let rx = host_wrap(&keys, 3, &command, &mut buffer).map_err(|_| ())?;
+        receive(&mut comm, &buffer[..rx]);
+        comm.append(&[0xaa; 300]);
+        comm.reply_ok();
+        assert_eq!(receive(&mut comm, &get_response).is_none(), true);
+        let sw = Reply::from(SyscallError::Security).0.to_be_bytes();
+        assert_eq!(comm.transport.response[..comm.transport.len], sw);
+        let pending = comm.response.as_ref().map(|r| r.is_pending());
+        assert_eq!(pending, Some(false));
+    }
+
+    #[test]
+    fn secure_channel_hkdf() {
+        // RFC 5869, test case 1
+        let salt: [u8; 13] = core::array::from_fn(|i| i as u8);
+        let info: [u8; 10] = core::array::from_fn(|i| 0xf0 + i as u8);
+        let prk = hkdf_extract(&salt, &[&[0x0b; 11], &[0x0b; 11]]).map_err(|_| ())?;
+        let expected = [
+            0x07, 0x77, 0x09, 0x36, 0x2c, 0x2e, 0x32, 0xdf, 0x0d, 0xdc, 0x3f, 0x0d, 0xc4, 0x7b,
+            0xba, 0x63, 0x90, 0xb6, 0xc7, 0x3b, 0xb5, 0x0f, 0x9c, 0x31, 0x22, 0xec, 0x84, 0x4a,
+            0xd7, 0xc2, 0xb3, 0xe5,
+        ];
+        assert_eq!(&prk, &expected);
+
+        let okm = hkdf_expand(&prk, &info).map_err(|_| ())?;
+        let expected = [
+            0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36,
+            0x2f, 0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56,
+            0xec, 0xc4, 0xc5, 0xbf,
+        ];
+        assert_eq!(&okm, &expected);
+    }
+
+    #[test]
+    fn secure_channel_aead() {
+        let key: [u8; KEY_LEN] = core::array::from_fn(|i| 0x80 + i as u8);
+        let mut data = *b"Not your keys, not your coins";
+        let tag = Aead::new(&key)
+            .and_then(|mut aead| aead.encrypt(7, &mut data))
+            .map_err(|_| ())?;
+        let expected = [
+            0x80, 0x56, 0x49, 0x85, 0x2b, 0x69, 0xdb, 0x3d, 0xbb, 0xa2, 0xc9, 0x10, 0xd5, 0x56,
+            0x1a, 0x9e, 0xda, 0x49, 0xb7, 0xe7, 0xcc, 0xf3, 0x24, 0x3c, 0x36, 0x6a, 0x30, 0xf2,
+            0xff,
+        ];
+        assert_eq!(&data, &expected);
+        let expected = [
+            0x01, 0xcf, 0x16, 0xe7, 0x26, 0x32, 0xb8, 0x16, 0x37, 0xcf, 0x05, 0x6b, 0x32, 0xc7,
+            0x2c, 0x0b,
+        ];
+        assert_eq!(&tag, &expected);
+
+        // Messages of another counter fail authentication
+        let mut aead = Aead::new(&key).map_err(|_| ())?;
+        let mut copy = data;
+        assert_eq!(aead.decrypt(8, &mut copy, &tag).is_err(), true);
+        aead.decrypt(7, &mut data, &tag).map_err(|_| ())?;
+        assert_eq!(&data, b"Not your keys, not your coins");
+    }
+
+    #[test]
+    fn secure_channel() {
+        let identity = Secp256r1::from(&IDENTITY);
+        let identity_pk = identity.public_key().map_err(|_| ())?;
+        let mut channel = SecureChannel::new(0xe1, identity).set_required(true);
+        let mut buffer = [0u8; 260];
+
+        // Plaintext commands are rejected
+        buffer[..4].copy_from_slice(&[0xe0, 0x01, 0x00, 0x00]);
+        let res = channel.filter(&mut buffer, 4);
+        assert_eq!(
+            matches!(res, Incoming::Handled(0, r) if r == SyscallError::Security.into()),
+            true
+        );
+
+        // Handshake
+        let host = Secp256r1::from(&HOST);
+        let host_pk = host.public_key().map_err(|_| ())?;
+        buffer[..DATA_OFFSET].copy_from_slice(&[0xe1, 0x01, 0x00, 0x00, POINT_LEN as u8]);
+        buffer[DATA_OFFSET..DATA_OFFSET + POINT_LEN].copy_from_slice(&host_pk.pubkey);
+        let res = channel.filter(&mut buffer, DATA_OFFSET + POINT_LEN);
+        assert_eq!(matches!(res, Incoming::Handled(POINT_LEN, _)), true);
+        let keys = host_keys(&host, &identity_pk.pubkey, &buffer[..POINT_LEN])?;
+
+        // Wrapped command and response
+        let apdu = [0xe0, 0x03, 0x00, 0x00, 0x02, 0xaa, 0xbb];
+        let rx = host_wrap(&keys, 1, &apdu, &mut buffer).map_err(|_| ())?;
+        let res = channel.filter(&mut buffer, rx);
+        assert_eq!(matches!(res, Incoming::Unwrapped(7)), true);
+        assert_eq!(&buffer[..7], &apdu);
+        assert_eq!(channel.is_wrapping(), true);
+
+        buffer[0] = 0x01;
+        let tx = channel.wrap(&mut buffer, 1, 0x9000).map_err(|_| ())?;
+        assert_eq!(tx, 1 + SecureChannel::OVERHEAD);
+        let (body, tag) = buffer[..tx].split_at_mut(3);
+        Aead::new(&keys.rsp)
+            .and_then(|mut aead| aead.decrypt(1, body, tag))
+            .map_err(|_| ())?;
+        assert_eq!(&buffer[..3], &[0x01, 0x90, 0x00]);
+
+        // Invalid handshakes leave the session untouched
+        buffer[..DATA_OFFSET].copy_from_slice(&[0xe1, 0x01, 0x00, 0x00, 0x01]);
+        let res = channel.filter(&mut buffer, DATA_OFFSET + 1);
+        assert_eq!(
+            matches!(res, Incoming::Handled(0, r) if r == StatusWords::BadLen.into()),
+            true
+        );
+        buffer[..DATA_OFFSET].copy_from_slice(&[0xe1, 0x01, 0x00, 0x00, POINT_LEN as u8]);
+        buffer[DATA_OFFSET..DATA_OFFSET + POINT_LEN].fill(0x04);
+        let res = channel.filter(&mut buffer, DATA_OFFSET + POINT_LEN);
+        assert_eq!(matches!(res, Incoming::Handled(0, _)), true);
+        assert_eq!(channel.is_open(), true);
+        let rx = host_wrap(&keys, 2, &apdu, &mut buffer).map_err(|_| ())?;
+        let res = channel.filter(&mut buffer, rx);
+        assert_eq!(matches!(res, Incoming::Unwrapped(7)), true);
+
+        // Replayed and tampered commands are rejected
+        let rx = host_wrap(&keys, 2, &apdu, &mut buffer).map_err(|_| ())?;
+        let res = channel.filter(&mut buffer, rx);
+        assert_eq!(
+            matches!(res, Incoming::Handled(0, r) if r == SyscallError::InvalidCounter.into()),
+            true
+        );
+        let rx = host_wrap(&keys, 3, &apdu, &mut buffer).map_err(|_| ())?;
+        buffer[DATA_OFFSET + COUNTER_LEN] ^= 0x01;
+        let res = channel.filter(&mut buffer, rx);
+        assert_eq!(
+            matches!(res, Incoming::Handled(0, r) if r == SyscallError::Security.into()),
+            true
+        );
+    }
+}
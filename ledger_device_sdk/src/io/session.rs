@@ -0,0 +1,235 @@
+//! State machine for transactions spanning several APDUs.
+
+use super::{ApduHeader, Comm, Reply, StatusWords, Transport};
+use crate::hash::HashInit;
+
+/// Instructions of the commands of a transaction: a first command with instruction `start`, any
+/// number of commands with instruction `next`, then a last command with instruction `finish`.
+///
+/// Several steps may share the same instruction, in which case the step is usually given by
+/// another parameter such as P1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Flow<T> {
+    pub start: T,
+    pub next: T,
+    pub finish: T,
+}
+
+/// Multi-APDU transaction, in which the data of the commands is hashed.
+///
+/// A transaction is started by [`Session::start`] with its [`Flow`], continued by
+/// [`Session::update`] and completed by [`Session::finish`], which returns the digest of all the
+/// data received. The session tracks the instructions expected next: continuing a transaction
+/// which has not been started, or with an instruction which is not the next step of its flow, is
+/// rejected with [`StatusWords::UnexpectedApdu`], and exceeding the maximum length of the
+/// transaction is rejected with [`StatusWords::BadLen`]. In both cases, the transaction is
+/// aborted and must be started again.
+///
+/// Any other command received during a transaction also aborts it, when commands are received
+/// with [`Session::next_command`] or passed to [`Session::check`].
+///
+/// `T` is the type used to identify the instruction of a command, usually the instruction
+/// enumeration of the application.
+///
+/// # Examples
+///
+/// ```
+/// const SIGN_TX: Flow<Instruction> = Flow {
+///     start: Instruction::SignTxInit,
+///     next: Instruction::SignTxChunk,
+///     finish: Instruction::SignTxFinal,
+/// };
+///
+/// let mut session = Session::<Instruction, Sha2_256>::new(MAX_TX_LEN);
+/// loop {
+///     // Aborts the transaction in progress on any command which is not part of it
+///     let ins = session.next_command(&mut comm);
+///     let result = match ins {
+///         Instruction::SignTxInit => session.start(SIGN_TX, comm.get_data()?),
+///         Instruction::SignTxChunk => session.update(ins, comm.get_data()?),
+///         Instruction::SignTxFinal => {
+///             let mut digest = [0u8; 32];
+///             session
+///                 .finish(ins, comm.get_data()?, &mut digest)
+///                 .and_then(|()| sign_and_reply(&mut comm, &digest))
+///         }
+///         Instruction::GetVersion => handle_get_version(&mut comm),
+///     };
+///     comm.reply_result(result);
+/// }
+/// ```
+pub struct Session<T, H> {
+    hasher: H,
+    flow: Option<Flow<T>>,
+    len: usize,
+    max_len: usize,
+}
+
+impl<T: Copy + PartialEq, H: HashInit> Session<T, H> {
+    /// Creates a new session, accepting transactions of at most `max_len` bytes of data.
+    pub fn new(max_len: usize) -> Self {
+        Session {
+            hasher: H::new(),
+            flow: None,
+            len: 0,
+            max_len,
+        }
+    }
+
+    /// Returns the flow of the transaction in progress, if any.
+    pub fn current(&self) -> Option<Flow<T>> {
+        self.flow
+    }
+
+    /// Returns the length of the data received in the transaction in progress.
+    pub fn received(&self) -> usize {
+        self.len
+    }
+
+    /// Starts a new transaction following `flow`, with the data of its first command.
+    ///
+    /// Any transaction in progress is discarded.
+    pub fn start(&mut self, flow: Flow<T>, data: &[u8]) -> Result<(), Reply> {
+        self.abort();
+        self.flow = Some(flow);
+        self.feed(data)
+    }
+
+    /// Continues the transaction in progress with the data of its next command, of instruction
+    /// `ins`.
+    pub fn update(&mut self, ins: T, data: &[u8]) -> Result<(), Reply> {
+        self.expect(|flow| flow.next == ins)?;
+        self.feed(data)
+    }
+
+    /// Completes the transaction in progress with the data of its last command, of instruction
+    /// `ins`, and writes the digest of all the data of the transaction in `digest`.
+    pub fn finish(&mut self, ins: T, data: &[u8], digest: &mut [u8]) -> Result<(), Reply> {
+        self.expect(|flow| flow.finish == ins)?;
+        self.feed(data)?;
+        let res = self.hasher.finalize(digest);
+        self.abort();
+        res.map_err(Reply::from)
+    }
+
+    /// Aborts the transaction in progress, if any.
+    pub fn abort(&mut self) {
+        self.hasher.reset();
+        self.flow = None;
+        self.len = 0;
+    }
+
+    /// Aborts the transaction in progress if `ins` is not one of the instructions of its flow.
+    /// Returns true if the transaction has been aborted.
+    ///
+    /// This must be called with every command received, unless they are received with
+    /// [`Session::next_command`].
+    pub fn check(&mut self, ins: T) -> bool {
+        match self.flow {
+            Some(flow) if ins != flow.start && ins != flow.next && ins != flow.finish => {
+                self.abort();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Waits for the next command with [`Comm::next_command`], and checks it with
+    /// [`Session::check`].
+    pub fn next_command<Tr: Transport, const N: usize>(&mut self, comm: &mut Comm<Tr, N>) -> T
+    where
+        T: TryFrom<ApduHeader>,
+        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+    {
+        let ins = comm.next_command();
+        self.check(ins);
+        ins
+    }
+
+    /// Checks that a transaction accepting the command is in progress, aborting it otherwise.
+    fn expect(&mut self, accepts: impl Fn(&Flow<T>) -> bool) -> Result<(), Reply> {
+        match &self.flow {
+            Some(flow) if accepts(flow) => Ok(()),
+            _ => {
+                self.abort();
+                Err(StatusWords::UnexpectedApdu.into())
+            }
+        }
+    }
+
+    /// Hashes data of the transaction in progress, aborting it if its maximum length is exceeded.
+    fn feed(&mut self, data: &[u8]) -> Result<(), Reply> {
+        if data.len() > self.max_len - self.len {
+            self.abort();
+            return Err(StatusWords::BadLen.into());
+        }
+        if let Err(e) = self.hasher.update(data) {
+            self.abort();
+            return Err(e.into());
+        }
+        self.len += data.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::hash::sha2::Sha2_256;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    const FLOW: Flow<u8> = Flow {
+        start: 0x04,
+        next: 0x05,
+        finish: 0x06,
+    };
+
+    #[test]
+    fn session_digest() {
+        let mut session = Session::<u8, Sha2_256>::new(32);
+        let mut digest = [0u8; 32];
+
+        assert_eq!(session.start(FLOW, b"Not your keys, "), Ok(()));
+        assert_eq!(session.update(0x05, b"not your "), Ok(()));
+        assert_eq!(session.received(), 24);
+        assert_eq!(session.finish(0x06, b"coins", &mut digest), Ok(()));
+        assert_eq!(session.current(), None);
+
+        let expected = [
+            0x52, 0x49, 0x2e, 0x81, 0x92, 0x16, 0xf3, 0x6b, 0x74, 0x7d, 0xd5, 0xda, 0x70, 0x3a,
+            0x26, 0x60, 0x14, 0x34, 0x60, 0x42, 0x42, 0xfa, 0xb2, 0x7e, 0x85, 0x51, 0xe7, 0x82,
+            0xa5, 0x11, 0x13, 0x40,
+        ];
+        assert_eq!(&digest, &expected);
+    }
+
+    #[test]
+    fn session_deviation() {
+        let mut session = Session::<u8, Sha2_256>::new(8);
+        let mut digest = [0u8; 32];
+        let unexpected: Result<(), Reply> = Err(StatusWords::UnexpectedApdu.into());
+
+        assert_eq!(session.update(0x05, b"data"), unexpected);
+        assert_eq!(session.start(FLOW, b"data"), Ok(()));
+        assert_eq!(session.finish(0x05, b"data", &mut digest), unexpected);
+        assert_eq!(session.current(), None);
+
+        assert_eq!(session.start(FLOW, b"data"), Ok(()));
+        assert_eq!(
+            session.update(0x05, b"more data"),
+            Err(StatusWords::BadLen.into())
+        );
+        assert_eq!(session.update(0x05, b"data"), unexpected);
+
+        // Instructions of the flow are left to the handlers, any other one aborts it
+        assert_eq!(session.start(FLOW, b"data"), Ok(()));
+        assert_eq!(session.check(0x05), false);
+        assert_eq!(session.check(0x04), false);
+        assert_eq!(session.current(), Some(FLOW));
+        assert_eq!(session.check(0x01), true);
+        assert_eq!(session.current(), None);
+        assert_eq!(session.update(0x05, b"data"), unexpected);
+    }
+}
@@ -0,0 +1,114 @@
+//! Application-defined status words.
+
+/// Returns true if `sw` is a status word used by the SDK, which applications may not redefine.
+///
+/// Reserved status words are:
+/// * `0x9000`, `0x6d00`, `0x6982`, `0x5515` and `0xe000` (see [`StatusWords`](super::StatusWords)),
+/// * `0x61xx`, used by response chaining,
+/// * `0x68xx`, used by [`SyscallError`](super::SyscallError) and command chaining,
+/// * `0x6exx`, used for malformed APDUs.
+pub const fn is_reserved_status_word(sw: u16) -> bool {
+    matches!(sw, 0x9000 | 0x6d00 | 0x6982 | 0x5515 | 0xe000)
+        || matches!(sw >> 8, 0x61 | 0x68 | 0x6e)
+}
+
+/// Declares an enumeration of application-specific status words.
+///
+/// The enumeration can be converted to a [`Reply`](crate::io::Reply), so it can be given to
+/// [`Comm::reply`](crate::io::Comm::reply) or propagated with `?` in handlers returning
+/// `Result<T, Reply>` (see [`Comm::reply_result`](crate::io::Comm::reply_result)). Compilation
+/// fails if a variant uses a status word reserved by the SDK (see
+/// [`is_reserved_status_word`](crate::io::is_reserved_status_word)).
+///
+/// # Examples
+///
+/// ```
+/// status_words! {
+///     /// Errors of the application
+///     pub enum AppSW {
+///         Deny = 0x6985,
+///         TxParsingFail = 0xb005,
+///     }
+/// }
+///
+/// fn handle_sign_tx(comm: &mut Comm) -> Result<(), Reply> {
+///     let mut reader = comm.reader()?;
+///     let tx = Transaction::parse(&mut reader).ok_or(AppSW::TxParsingFail)?;
+///     if !ui_validate(&tx) {
+///         return Err(AppSW::Deny.into());
+///     }
+///     ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! status_words {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident = $sw:expr),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        #[repr(u16)]
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant = $sw),*
+        }
+
+        const _: () = {
+            $(assert!(
+                !$crate::io::is_reserved_status_word($sw),
+                concat!("status word of `", stringify!($variant), "` is reserved by the SDK")
+            );)*
+        };
+
+        impl From<$name> for $crate::io::Reply {
+            fn from(sw: $name) -> $crate::io::Reply {
+                $crate::io::Reply(sw as u16)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io::{Comm, Reply, StatusWords};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    crate::status_words! {
+        enum TestSW {
+            Deny = 0x6985,
+            TxParsingFail = 0xb005,
+        }
+    }
+
+    fn handle(comm: &mut Comm) -> Result<(), Reply> {
+        let mut reader = comm.reader()?;
+        match reader.read_u8()? {
+            0 => Err(TestSW::Deny.into()),
+            1 => Err(TestSW::TxParsingFail.into()),
+            _ => Ok(()),
+        }
+    }
+
+    #[test]
+    fn app_status_words() {
+        assert_eq!(is_reserved_status_word(0x9000), true);
+        assert_eq!(is_reserved_status_word(0x6e05), true);
+        assert_eq!(is_reserved_status_word(0x6985), false);
+        assert_eq!(Reply::from(TestSW::TxParsingFail), Reply(0xb005));
+
+        let mut comm = Comm::new();
+        comm.apdu_buffer[..4].copy_from_slice(&[0xe0, 0x01, 0x00, 0x00]);
+        comm.rx = 4;
+        assert_eq!(handle(&mut comm), Err(StatusWords::BadLen.into()));
+        comm.apdu_buffer[4..6].copy_from_slice(&[0x01, 0x00]);
+        comm.rx = 6;
+        assert_eq!(handle(&mut comm), Err(TestSW::Deny.into()));
+        comm.apdu_buffer[5] = 0x02;
+        assert_eq!(handle(&mut comm), Ok(()));
+    }
+}
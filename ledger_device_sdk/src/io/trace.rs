@@ -0,0 +1,255 @@
+//! Trace of the APDUs exchanged by a [`Comm`](super::Comm), enabled with the `trace` feature.
+
+use crate::testing::{debug_print, to_hex};
+
+/// Number of APDUs kept in a [`Trace`].
+pub const TRACE_CAPACITY: usize = 16;
+
+/// Length of a serialized [`TraceEntry`].
+pub const TRACE_ENTRY_LEN: usize = 18;
+
+/// Command received and replied by a [`Comm`](super::Comm).
+///
+/// Times are counted in [`Event::Ticker`](super::Event::Ticker) events received by the
+/// [`Comm`](super::Comm).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// CLA, INS, P1 and P2 of the command
+    pub header: [u8; 4],
+    /// Length of the command, including its header
+    pub command_len: u16,
+    /// Length of the response data, without the status word
+    pub response_len: u16,
+    /// Status word of the response
+    pub sw: u16,
+    /// Time at which the command has been received
+    pub received: u32,
+    /// Time at which the response has been transmitted
+    pub replied: u32,
+}
+
+impl TraceEntry {
+    const EMPTY: TraceEntry = TraceEntry {
+        header: [0; 4],
+        command_len: 0,
+        response_len: 0,
+        sw: 0,
+        received: 0,
+        replied: 0,
+    };
+
+    /// Writes the entry to `out`, with big-endian integers in the order of the fields.
+    pub fn serialize(&self, out: &mut [u8; TRACE_ENTRY_LEN]) {
+        out[..4].copy_from_slice(&self.header);
+        out[4..6].copy_from_slice(&self.command_len.to_be_bytes());
+        out[6..8].copy_from_slice(&self.response_len.to_be_bytes());
+        out[8..10].copy_from_slice(&self.sw.to_be_bytes());
+        out[10..14].copy_from_slice(&self.received.to_be_bytes());
+        out[14..18].copy_from_slice(&self.replied.to_be_bytes());
+    }
+}
+
+/// Ring buffer holding the last [`TRACE_CAPACITY`] APDUs exchanged by a
+/// [`Comm`](super::Comm), returned by [`Comm::trace`](super::Comm::trace).
+///
+/// Commands are recorded once they are replied, including the ones answered by the
+/// [`Comm`](super::Comm) itself (BOLOS commands, rejected commands, ...).
+///
+/// The trace can be printed with [`Trace::dump`], or read by the host with a dedicated command
+/// enabled with [`Comm::set_trace_command`](super::Comm::set_trace_command).
+pub struct Trace {
+    entries: [TraceEntry; TRACE_CAPACITY],
+    /// Index of the next entry to write
+    next: usize,
+    len: usize,
+    /// Command received and not replied yet
+    pending: Option<TraceEntry>,
+    /// Number of ticker events received
+    ticks: u32,
+    /// CLA and INS of the command returning the trace
+    pub(crate) command: Option<(u8, u8)>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trace {
+    pub const fn new() -> Self {
+        Trace {
+            entries: [TraceEntry::EMPTY; TRACE_CAPACITY],
+            next: 0,
+            len: 0,
+            pending: None,
+            ticks: 0,
+            command: None,
+        }
+    }
+
+    /// Returns the number of entries in the trace.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no APDU has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the entries of the trace, from the oldest to the most recent one.
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        let first = (self.next + TRACE_CAPACITY - self.len) % TRACE_CAPACITY;
+        (0..self.len).map(move |i| &self.entries[(first + i) % TRACE_CAPACITY])
+    }
+
+    /// Removes all the entries of the trace.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+
+    /// Prints the trace with [`debug_print`], one entry per line from the oldest one: header,
+    /// command length, response length, status word, reception and response times, in
+    /// hexadecimal.
+    pub fn dump(&self) {
+        debug_print("--- APDU trace ---\n");
+        for entry in self.iter() {
+            let header = u32::from_be_bytes(entry.header);
+            let fields = [
+                (header, 8),
+                (entry.command_len as u32, 4),
+                (entry.response_len as u32, 4),
+                (entry.sw as u32, 4),
+                (entry.received, 8),
+                (entry.replied, 8),
+            ];
+            for (value, digits) in fields {
+                let hex = to_hex(value);
+                debug_print(core::str::from_utf8(&hex[8 - digits..]).unwrap());
+                debug_print(" ");
+            }
+            debug_print("\n");
+        }
+    }
+
+    /// Writes the number of entries, then the serialized entries starting from the `first`
+    /// oldest one which fit in `out`. Returns the length written.
+    pub(crate) fn serialize(&self, first: usize, out: &mut [u8]) -> usize {
+        if out.is_empty() {
+            return 0;
+        }
+        out[0] = self.len as u8;
+        let mut len = 1;
+        for entry in self.iter().skip(first) {
+            match out.get_mut(len..len + TRACE_ENTRY_LEN) {
+                Some(chunk) => entry.serialize(chunk.try_into().unwrap()),
+                None => break,
+            }
+            len += TRACE_ENTRY_LEN;
+        }
+        len
+    }
+
+    pub(crate) fn tick(&mut self) {
+        self.ticks = self.ticks.wrapping_add(1);
+    }
+
+    /// Records the reception of `apdu`.
+    pub(crate) fn command(&mut self, apdu: &[u8]) {
+        let mut header = [0u8; 4];
+        let len = apdu.len().min(4);
+        header[..len].copy_from_slice(&apdu[..len]);
+        self.pending = Some(TraceEntry {
+            header,
+            command_len: apdu.len() as u16,
+            received: self.ticks,
+            ..TraceEntry::EMPTY
+        });
+    }
+
+    /// Records the response to the pending command, with `len` bytes of data and `sw`.
+    pub(crate) fn response(&mut self, len: usize, sw: u16) {
+        let mut entry = self.pending.take().unwrap_or(TraceEntry {
+            received: self.ticks,
+            ..TraceEntry::EMPTY
+        });
+        entry.response_len = len as u16;
+        entry.sw = sw;
+        entry.replied = self.ticks;
+
+        self.entries[self.next] = entry;
+        self.next = (self.next + 1) % TRACE_CAPACITY;
+        self.len = (self.len + 1).min(TRACE_CAPACITY);
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::io::{ApduHeader, Comm, Event};
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn apdu_trace() {
+        let mut comm = Comm::new()
+            .set_expected_cla(0xe0)
+            .set_trace_command(0xe0, 0xfe);
+        comm.transport
+            .push_command(&[0xe0, 0x02, 0x00, 0x00, 0x01, 0xaa]);
+        comm.transport.push_ticker();
+        comm.transport.push_ticker();
+        comm.transport.push_command(&[0xe1, 0x02, 0x00, 0x00]);
+        comm.transport.push_command(&[0xe0, 0xfe, 0x01, 0x00]);
+        comm.transport.push_ticker();
+
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Command(h) if h.ins == 0x02), true);
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+        comm.append(&[0x01, 0x02]);
+        comm.reply_ok();
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+        let event = comm.next_event::<ApduHeader>();
+        assert_eq!(matches!(event, Event::Ticker), true);
+
+        let entries = [
+            TraceEntry {
+                header: [0xe0, 0x02, 0x00, 0x00],
+                command_len: 6,
+                response_len: 2,
+                sw: 0x9000,
+                received: 0,
+                replied: 1,
+            },
+            TraceEntry {
+                header: [0xe1, 0x02, 0x00, 0x00],
+                command_len: 4,
+                response_len: 0,
+                sw: 0x6e00,
+                received: 2,
+                replied: 2,
+            },
+        ];
+        let trace = comm.trace();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace.iter().take(2).eq(entries.iter()), true);
+
+        // The trace command returned the entries from the second one
+        comm.transport.pop_response();
+        comm.transport.pop_response();
+        let response = comm.transport.pop_response().ok_or(())?;
+        let mut expected = [0u8; TRACE_ENTRY_LEN];
+        entries[1].serialize(&mut expected);
+        assert_eq!(response.len(), 1 + TRACE_ENTRY_LEN + 2);
+        assert_eq!(response[0], 2);
+        assert_eq!(response[1..1 + TRACE_ENTRY_LEN], expected);
+
+        comm.trace().clear();
+        assert_eq!(comm.trace().is_empty(), true);
+    }
+}
@@ -0,0 +1,262 @@
+//! Transports used by [`Comm`](super::Comm) to receive events and transmit responses.
+
+#[cfg(all(
+    any(target_os = "nanox", target_os = "stax", target_os = "flex"),
+    not(feature = "mock")
+))]
+use crate::ble;
+#[cfg(all(feature = "ccid", not(feature = "mock")))]
+use crate::ccid;
+#[cfg(not(feature = "mock"))]
+use crate::seph;
+#[cfg(all(
+    not(any(target_os = "stax", target_os = "flex")),
+    not(feature = "mock")
+))]
+use ledger_secure_sdk_sys::buttons::{get_button_event, ButtonsState};
+#[cfg(not(feature = "mock"))]
+use ledger_secure_sdk_sys::seph as sys_seph;
+#[cfg(not(feature = "mock"))]
+use ledger_secure_sdk_sys::*;
+
+#[cfg(not(feature = "mock"))]
+use super::ConnectionEvent;
+use super::Event;
+use core::convert::Infallible;
+
+/// Low-level I/O used by [`Comm`](super::Comm).
+///
+/// A transport receives event packets, decodes them into events and APDUs, and transmits
+/// responses. Implementing this trait allows adding new transports, instrumenting the I/O of
+/// an application (by wrapping another transport), or substituting an in-memory transport in
+/// tests.
+pub trait Transport {
+    /// Waits for the next event packet, and writes it in `packet`.
+    fn recv(&mut self, packet: &mut [u8; 128]);
+
+    /// Processes an event packet received with [`Transport::recv`].
+    ///
+    /// APDU data carried by the packet is written in `apdu_buffer`. Events which must be reported
+    /// to the application (buttons, ticker...) are returned, received APDUs are reported by
+    /// [`Transport::received`].
+    fn process(
+        &mut self,
+        packet: &mut [u8; 128],
+        apdu_buffer: &mut [u8],
+    ) -> Option<Event<Infallible>>;
+
+    /// Returns the length of the APDU held in the APDU buffer, if a complete APDU has been
+    /// received and is waiting for a response.
+    fn received(&self) -> Option<usize>;
+
+    /// Transmits the response held in the first `len` bytes of `apdu_buffer`, and gets ready to
+    /// receive the next APDU.
+    fn send(&mut self, apdu_buffer: &mut [u8], len: usize);
+
+    /// Discards the received APDU, if any, and gets ready to receive the next one.
+    fn reset(&mut self);
+
+    /// Returns true if the device is locked, i.e. the PIN has not been validated since the
+    /// device has been locked.
+    #[cfg(not(feature = "mock"))]
+    fn is_device_locked(&self) -> bool {
+        crate::uxapp::is_device_locked()
+    }
+
+    /// Returns true if the device is locked. The device is never locked with the `mock` feature,
+    /// unless the transport reports it.
+    #[cfg(feature = "mock")]
+    fn is_device_locked(&self) -> bool {
+        false
+    }
+
+    /// Asks the user to validate the PIN, which unlocks the device if it succeeds.
+    #[cfg(not(feature = "mock"))]
+    fn validate_pin(&mut self) {
+        crate::uxapp::UxEvent::ValidatePIN.request();
+    }
+
+    /// Asks the user to validate the PIN. Does nothing with the `mock` feature, unless the
+    /// transport simulates it.
+    #[cfg(feature = "mock")]
+    fn validate_pin(&mut self) {}
+}
+
+/// Default transport, exchanging events with the MCU through the SEPROXYHAL protocol.
+///
+/// APDUs are received and transmitted through the media the command has been received from:
+/// USB HID, raw APDUs (used by Speculos), USB CCID (with the `ccid` feature) and BLE (on
+/// devices supporting it).
+#[cfg(not(feature = "mock"))]
+pub struct SephTransport {
+    #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+    buttons: ButtonsState,
+}
+
+#[cfg(not(feature = "mock"))]
+impl Default for SephTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(feature = "mock"))]
+impl SephTransport {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+            buttons: ButtonsState::new(),
+        }
+    }
+}
+
+#[cfg(not(feature = "mock"))]
+impl Transport for SephTransport {
+    fn recv(&mut self, packet: &mut [u8; 128]) {
+        // Signal end of command stream from SE to MCU
+        // And prepare reception
+        if !sys_seph::is_status_sent() {
+            sys_seph::send_general_status();
+        }
+
+        // Fetch the next message from the MCU
+        let _rx = sys_seph::seph_recv(packet, 0);
+    }
+
+    fn process(
+        &mut self,
+        packet: &mut [u8; 128],
+        apdu_buffer: &mut [u8],
+    ) -> Option<Event<Infallible>> {
+        // message = [ tag, len_hi, len_lo, ... ]
+        let tag = packet[0];
+        let len = u16::from_be_bytes([packet[1], packet[2]]);
+
+        // XXX: check whether this is necessary
+        // if rx < 3 && rx != len+3 {
+        //     unsafe {
+        //        G_io_app.apdu_state = APDU_IDLE;
+        //        G_io_app.apdu_length = 0;
+        //     }
+        //     return None
+        // }
+
+        // Treat all possible events.
+        // If this is a button push, return with the associated event
+        // Any other event (usb, xfer, ticker) is silently handled
+        match seph::Events::from(tag) {
+            #[cfg(not(any(target_os = "stax", target_os = "flex")))]
+            seph::Events::ButtonPush => {
+                let button_info = packet[3] >> 1;
+                if let Some(btn_evt) = get_button_event(&mut self.buttons, button_info) {
+                    return Some(Event::Button(btn_evt));
+                }
+            }
+            seph::Events::USBEvent => {
+                if len == 1 {
+                    seph::handle_usb_event(packet[3]);
+                    match seph::Events::from(packet[3]) {
+                        seph::Events::USBEventReset => {
+                            return Some(Event::Connection(ConnectionEvent::UsbReset))
+                        }
+                        seph::Events::USBEventSuspend => {
+                            return Some(Event::Connection(ConnectionEvent::UsbSuspend))
+                        }
+                        seph::Events::USBEventResume => {
+                            return Some(Event::Connection(ConnectionEvent::UsbResume))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            seph::Events::USBXFEREvent => {
+                if len >= 3 {
+                    seph::handle_usb_ep_xfer_event(apdu_buffer, packet);
+                }
+            }
+            seph::Events::CAPDUEvent => seph::handle_capdu_event(apdu_buffer, packet),
+
+            #[cfg(any(target_os = "nanox", target_os = "stax", target_os = "flex"))]
+            seph::Events::BleReceive => {
+                ble::receive(apdu_buffer, packet);
+                if let Some(event) = ble::connection_event(packet) {
+                    return Some(Event::Connection(event));
+                }
+            }
+
+            seph::Events::TickerEvent => {
+                #[cfg(any(target_os = "stax", target_os = "flex"))]
+                unsafe {
+                    ux_process_ticker_event();
+                }
+                return Some(Event::Ticker);
+            }
+
+            #[cfg(any(target_os = "stax", target_os = "flex"))]
+            seph::Events::ScreenTouch => unsafe {
+                ux_process_finger_event(packet.as_mut_ptr());
+                return Some(Event::TouchEvent);
+            },
+
+            _ => {
+                #[cfg(any(target_os = "stax", target_os = "flex"))]
+                unsafe {
+                    ux_process_default_event();
+                }
+            }
+        }
+        None
+    }
+
+    fn received(&self) -> Option<usize> {
+        if unsafe { G_io_app.apdu_state } != APDU_IDLE && unsafe { G_io_app.apdu_length } > 0 {
+            Some(unsafe { G_io_app.apdu_length as usize })
+        } else {
+            None
+        }
+    }
+
+    fn send(&mut self, apdu_buffer: &mut [u8], len: usize) {
+        if !sys_seph::is_status_sent() {
+            sys_seph::send_general_status()
+        }
+        let mut spi_buffer = [0u8; 128];
+        while sys_seph::is_status_sent() {
+            sys_seph::seph_recv(&mut spi_buffer, 0);
+            seph::handle_event(apdu_buffer, &spi_buffer);
+        }
+
+        match unsafe { G_io_app.apdu_state } {
+            APDU_USB_HID => unsafe {
+                ledger_secure_sdk_sys::io_usb_hid_send(
+                    Some(io_usb_send_apdu_data),
+                    len as u16,
+                    apdu_buffer.as_mut_ptr(),
+                );
+            },
+            APDU_RAW => {
+                let len_be = (len as u16).to_be_bytes();
+                sys_seph::seph_send(&[sys_seph::SephTags::RawAPDU as u8, len_be[0], len_be[1]]);
+                sys_seph::seph_send(&apdu_buffer[..len]);
+            }
+            #[cfg(feature = "ccid")]
+            APDU_USB_CCID => {
+                ccid::send(&apdu_buffer[..len]);
+            }
+            #[cfg(any(target_os = "nanox", target_os = "stax", target_os = "flex"))]
+            APDU_BLE => {
+                ble::send(&apdu_buffer[..len]);
+            }
+            _ => (),
+        }
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        unsafe {
+            G_io_app.apdu_state = APDU_IDLE;
+            G_io_app.apdu_media = IO_APDU_MEDIA_NONE;
+            G_io_app.apdu_length = 0;
+        }
+    }
+}
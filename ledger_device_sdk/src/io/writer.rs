@@ -0,0 +1,143 @@
+//! Overflow-safe builder for APDU responses.
+
+use super::{
+    Comm, DefaultTransport, Reply, StatusWords, SyscallError, Transport, APDU_BUFFER_SIZE,
+};
+
+/// Returned when trying to write more data than the response can hold.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResponseFullError;
+
+impl From<ResponseFullError> for Reply {
+    fn from(_: ResponseFullError) -> Reply {
+        SyscallError::Overflow.into()
+    }
+}
+
+/// Response builder borrowed from a [`Comm`] with [`Comm::writer`].
+///
+/// Data is appended after the data already held in the response. Two bytes are always kept
+/// available for the status word, and every write method returns a [`ResponseFullError`] if the
+/// data does not fit, in which case nothing is written.
+///
+/// If response chaining is enabled with [`Comm::set_response_buffer`], the capacity of the
+/// response is the size of the response buffer.
+///
+/// # Examples
+///
+/// ```
+/// let mut writer = comm.writer();
+/// writer.put_u8(pk.len() as u8)?;
+/// writer.put_slice(&pk)?;
+/// writer.put_lv(&chain_code)?;
+/// writer.reply_ok();
+/// ```
+pub struct ApduWriter<'a, Tr = DefaultTransport, const N: usize = APDU_BUFFER_SIZE> {
+    comm: &'a mut Comm<Tr, N>,
+}
+
+impl<'a, Tr: Transport, const N: usize> ApduWriter<'a, Tr, N> {
+    pub fn new(comm: &'a mut Comm<Tr, N>) -> Self {
+        ApduWriter { comm }
+    }
+
+    /// Returns the length of the response data written so far.
+    pub fn len(&self) -> usize {
+        self.comm.response_len()
+    }
+
+    /// Returns true if no response data has been written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes which can still be written.
+    pub fn remaining(&self) -> usize {
+        self.comm.response_capacity().saturating_sub(self.len())
+    }
+
+    pub fn put_slice(&mut self, data: &[u8]) -> Result<(), ResponseFullError> {
+        if data.len() > self.remaining() {
+            return Err(ResponseFullError);
+        }
+        self.comm.append(data);
+        Ok(())
+    }
+
+    pub fn put_u8(&mut self, value: u8) -> Result<(), ResponseFullError> {
+        self.put_slice(&[value])
+    }
+
+    pub fn put_u16_be(&mut self, value: u16) -> Result<(), ResponseFullError> {
+        self.put_slice(&value.to_be_bytes())
+    }
+
+    pub fn put_u16_le(&mut self, value: u16) -> Result<(), ResponseFullError> {
+        self.put_slice(&value.to_le_bytes())
+    }
+
+    pub fn put_u32_be(&mut self, value: u32) -> Result<(), ResponseFullError> {
+        self.put_slice(&value.to_be_bytes())
+    }
+
+    pub fn put_u32_le(&mut self, value: u32) -> Result<(), ResponseFullError> {
+        self.put_slice(&value.to_le_bytes())
+    }
+
+    pub fn put_u64_be(&mut self, value: u64) -> Result<(), ResponseFullError> {
+        self.put_slice(&value.to_be_bytes())
+    }
+
+    pub fn put_u64_le(&mut self, value: u64) -> Result<(), ResponseFullError> {
+        self.put_slice(&value.to_le_bytes())
+    }
+
+    /// Writes `data` prefixed with its length, encoded on one byte.
+    /// Returns an error if `data` is longer than 255 bytes.
+    pub fn put_lv(&mut self, data: &[u8]) -> Result<(), ResponseFullError> {
+        if data.len() > u8::MAX as usize || data.len() + 1 > self.remaining() {
+            return Err(ResponseFullError);
+        }
+        self.put_u8(data.len() as u8)?;
+        self.put_slice(data)
+    }
+
+    /// Transmits the response with the given status word (see [`Comm::reply`]).
+    pub fn reply<T: Into<Reply>>(self, reply: T) {
+        self.comm.reply(reply);
+    }
+
+    /// Transmits the response with [`StatusWords::Ok`].
+    pub fn reply_ok(self) {
+        self.comm.reply(StatusWords::Ok);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn writer_capacity() {
+        let mut comm = Comm::new();
+        let mut writer = comm.writer();
+        assert_eq!(writer.remaining(), 258);
+        assert_eq!(writer.put_u16_be(0x0102), Ok(()));
+        assert_eq!(writer.put_lv(&[0xaa; 254]), Ok(()));
+        assert_eq!(writer.remaining(), 1);
+        assert_eq!(writer.put_u16_le(0x0304), Err(ResponseFullError));
+        assert_eq!(writer.put_u8(0x05), Ok(()));
+        assert_eq!(writer.len(), 258);
+        assert_eq!(comm.tx, 258);
+        assert_eq!(comm.apdu_buffer[..3], [0x01, 0x02, 0xfe]);
+
+        let mut comm = Comm::<_, 64>::with_buffer_size(DefaultTransport::new());
+        let mut writer = comm.writer();
+        assert_eq!(writer.remaining(), 62);
+        assert_eq!(writer.put_slice(&[0xaa; 63]), Err(ResponseFullError));
+        assert_eq!(writer.put_slice(&[0xaa; 62]), Ok(()));
+    }
+}
@@ -1,5 +1,5 @@
 #![no_std]
-#![cfg_attr(test, no_main)]
+#![cfg_attr(all(test, not(feature = "mock")), no_main)]
 #![feature(custom_test_frameworks)]
 #![reexport_test_harness_main = "test_main"]
 #![test_runner(testing::sdk_test_runner)]
@@ -7,41 +7,77 @@
 #![feature(generic_const_exprs)]
 #![feature(cfg_version)]
 
-#[cfg(any(target_os = "nanox", target_os = "stax", target_os = "flex"))]
+// The host standard library is used by the mock transport and the test runner
+#[cfg(feature = "mock")]
+extern crate std;
+
+// Allows the derive macros, which refer to `::ledger_device_sdk`, to be tested here
+#[cfg(test)]
+extern crate self as ledger_device_sdk;
+
+#[cfg(all(
+    any(target_os = "nanox", target_os = "stax", target_os = "flex"),
+    not(feature = "mock")
+))]
 pub mod ble;
 
-#[cfg(feature = "ccid")]
+#[cfg(all(feature = "ccid", not(feature = "mock")))]
 pub mod ccid;
+#[cfg(not(feature = "mock"))]
 pub mod ecc;
+pub mod executor;
+#[cfg(not(feature = "mock"))]
 pub mod hash;
+#[cfg(not(feature = "mock"))]
 pub mod hmac;
 pub mod io;
+#[cfg(not(feature = "mock"))]
+pub mod libcall;
 pub mod nvm;
+#[cfg(not(feature = "mock"))]
 pub mod random;
+#[cfg(not(feature = "mock"))]
 pub mod screen;
+#[cfg(not(feature = "mock"))]
 pub mod seph;
+#[cfg(not(feature = "mock"))]
+pub mod swap;
+#[cfg(not(feature = "mock"))]
+pub mod token;
 
 pub mod testing;
 
-#[cfg(any(target_os = "stax", target_os = "flex"))]
+#[cfg(all(any(target_os = "stax", target_os = "flex"), not(feature = "mock")))]
 pub mod nbgl;
-#[cfg(not(any(target_os = "stax", target_os = "flex")))]
+#[cfg(all(
+    not(any(target_os = "stax", target_os = "flex")),
+    not(feature = "mock")
+))]
 pub mod ui;
 
+#[cfg(not(feature = "mock"))]
 pub mod uxapp;
 
+#[cfg(not(feature = "mock"))]
 use core::panic::PanicInfo;
 
 /// In case of runtime problems, return an internal error and exit the app
 #[inline]
+#[cfg(not(feature = "mock"))]
 pub fn exiting_panic(_info: &PanicInfo) -> ! {
     let mut comm = io::Comm::new();
     comm.reply(io::StatusWords::Panic);
     ledger_secure_sdk_sys::exit_app(0);
 }
 
+/// Name of the Rust SDK
+pub const RUST_SDK_NAME: &str = env!("CARGO_PKG_NAME");
+/// Version of the Rust SDK, reported by the BOLOS GetAppInfo command
+pub const RUST_SDK_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // re-export exit_app
 pub use ledger_secure_sdk_sys::buttons;
+#[cfg(not(feature = "mock"))]
 pub use ledger_secure_sdk_sys::exit_app;
 
 /// Helper macro that sets an external panic handler
@@ -57,15 +93,20 @@ macro_rules! set_panic {
     };
 }
 
+#[cfg(not(feature = "mock"))]
 extern "C" {
-    fn c_main();
+    fn c_main(arg0: u32);
 }
 
+/// Entry point of the application. `arg0` is 0 when the application is started normally, and
+/// `sample_main` is called, or points to the parameters of the call when it is called as a
+/// library, and `library_main` is called instead (see [`libcall_main!`]).
+#[cfg(not(feature = "mock"))]
 #[link_section = ".boot"]
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(arg0: u32) -> ! {
     // Main is in C until the try_context can be set properly from Rust
-    unsafe { c_main() };
+    unsafe { c_main(arg0) };
     ledger_secure_sdk_sys::exit_app(1);
 }
 
@@ -82,10 +123,12 @@ pub extern "C" fn _start() -> ! {
 /// // Access with address translation is enforced thanks to Pic wrapper
 /// let x: u32 = *DATA.get_ref();
 /// ```
+#[cfg(not(feature = "mock"))]
 pub struct Pic<T> {
     data: T,
 }
 
+#[cfg(not(feature = "mock"))]
 impl<T> Pic<T> {
     pub const fn new(data: T) -> Pic<T> {
         Pic { data }
@@ -103,6 +146,7 @@ impl<T> Pic<T> {
 }
 
 // Needed for `NVMData<T>` to function properly
+#[cfg(not(feature = "mock"))]
 extern "C" {
     // This is a linker script symbol defining the beginning of
     // the .nvm_data section. Declaring it as a static u32
@@ -115,10 +159,12 @@ extern "C" {
 
 /// The following is a means to correctly access data stored in NVM
 /// through the `#[link_section = ".nvm_data"]` attribute
+#[cfg(not(feature = "mock"))]
 pub struct NVMData<T> {
     data: T,
 }
 
+#[cfg(not(feature = "mock"))]
 impl<T> NVMData<T> {
     pub const fn new(data: T) -> NVMData<T> {
         NVMData { data }
@@ -166,7 +212,7 @@ impl<T> NVMData<T> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(feature = "mock")))]
 #[no_mangle]
 fn sample_main() {
     test_main();
@@ -0,0 +1,293 @@
+//! Calls between applications through `os_lib_call`.
+//!
+//! An application can expose commands to other installed applications, which call it as a
+//! library by its name. Requests and responses are serialized with [`LibCallData`] into a
+//! buffer of the caller, and the library replies with a status word.
+//!
+//! The caller uses [`call`]:
+//!
+//! ```
+//! let mut buffer = [0u8; 64];
+//! let balance: u64 = libcall::call(c"MyToken", GET_BALANCE, &account, &mut buffer)?;
+//! ```
+//!
+//! and the library implements [`LibCallProvider`], and defines the entry point of library calls
+//! with [`libcall_main!`](crate::libcall_main):
+//!
+//! ```
+//! struct Provider;
+//!
+//! impl LibCallProvider for Provider {
+//!     type Request = u32;
+//!     type Response = u64;
+//!
+//!     fn handle(command: u32, account: u32) -> Result<u64, Reply> {
+//!         match command {
+//!             GET_BALANCE => Ok(balance(account)),
+//!             _ => Err(StatusWords::BadIns.into()),
+//!         }
+//!     }
+//! }
+//!
+//! libcall_main!(provider: Provider);
+//! ```
+//!
+//! The RAM of the library overlaps the one of the caller, which resumes after the call: the RAM
+//! of the library is not initialized, and its handlers must not access global variables (other
+//! than constants) nor allocate. Library calls do not go through `sample_main`, so the code of
+//! the application which relies on its RAM is not run either.
+use crate::io::{Reply, StatusWords, SyscallError};
+use core::ffi::{c_void, CStr};
+use ledger_secure_sdk_sys::{lib_call, pic};
+
+/// Identifier of the calls made with [`call`], checked by [`lib_main`].
+pub const LIBCALL_ID: u32 = 0x4c43_0001;
+
+/// Arguments of a library call, as received by the library (`libargs_t`).
+#[repr(C)]
+pub(crate) struct LibArgs {
+    pub id: u32,
+    pub command: u32,
+    pub _unused: u32,
+    pub parameters: *mut u8,
+}
+
+/// Request and response of a call made with [`call`], in the buffer of the caller.
+#[repr(C)]
+struct CallFrame {
+    data: *mut u8,
+    len: usize,
+    capacity: usize,
+    status: u32,
+}
+
+/// Serialization of requests and responses of library calls.
+pub trait LibCallData: Sized {
+    /// Writes `self` to `out`, and returns the length written, or `None` if it does not fit.
+    fn serialize(&self, out: &mut [u8]) -> Option<usize>;
+    /// Reads a value from `data`, or returns `None` if it is invalid.
+    fn deserialize(data: &[u8]) -> Option<Self>;
+}
+
+impl LibCallData for () {
+    fn serialize(&self, _out: &mut [u8]) -> Option<usize> {
+        Some(0)
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        data.is_empty().then_some(())
+    }
+}
+
+macro_rules! impl_libcall_data {
+    ($($t:ty),*) => {
+        $(impl LibCallData for $t {
+            fn serialize(&self, out: &mut [u8]) -> Option<usize> {
+                let bytes = self.to_be_bytes();
+                out.get_mut(..bytes.len())?.copy_from_slice(&bytes);
+                Some(bytes.len())
+            }
+
+            fn deserialize(data: &[u8]) -> Option<Self> {
+                Some(<$t>::from_be_bytes(data.try_into().ok()?))
+            }
+        })*
+    };
+}
+
+impl_libcall_data!(u8, u16, u32, u64);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LibCallError {
+    /// The call raised an exception, for instance because the application is not installed.
+    Exception(u32),
+    /// The library replied with an error status word.
+    Status(Reply),
+    /// The request does not fit in the buffer.
+    RequestTooLarge,
+    /// The response of the library is invalid.
+    InvalidResponse,
+}
+
+impl From<LibCallError> for Reply {
+    fn from(e: LibCallError) -> Reply {
+        match e {
+            LibCallError::Exception(_) => SyscallError::NotSupported.into(),
+            LibCallError::Status(reply) => reply,
+            LibCallError::RequestTooLarge => SyscallError::Overflow.into(),
+            LibCallError::InvalidResponse => SyscallError::InvalidState.into(),
+        }
+    }
+}
+
+/// Calls the application named `app` as a library, with the command `command` and `request`.
+///
+/// `buffer` holds the serialized request, then the serialized response, and must be large enough
+/// for both.
+pub fn call<Req: LibCallData, Resp: LibCallData>(
+    app: &CStr,
+    command: u32,
+    request: &Req,
+    buffer: &mut [u8],
+) -> Result<Resp, LibCallError> {
+    let len = request
+        .serialize(buffer)
+        .ok_or(LibCallError::RequestTooLarge)?;
+    let mut frame = CallFrame {
+        data: buffer.as_mut_ptr(),
+        len,
+        capacity: buffer.len(),
+        status: 0,
+    };
+    let name = unsafe { pic(app.as_ptr() as *mut c_void) };
+    let mut parameters = [
+        name as u32,
+        LIBCALL_ID,
+        command,
+        0,
+        &mut frame as *mut CallFrame as u32,
+    ];
+    lib_call(&mut parameters).map_err(LibCallError::Exception)?;
+
+    if frame.status != StatusWords::Ok as u32 {
+        return Err(LibCallError::Status(Reply(frame.status as u16)));
+    }
+    let data = buffer
+        .get(..frame.len)
+        .ok_or(LibCallError::InvalidResponse)?;
+    Resp::deserialize(data).ok_or(LibCallError::InvalidResponse)
+}
+
+/// Commands exposed by an application to other applications.
+pub trait LibCallProvider {
+    type Request: LibCallData;
+    type Response: LibCallData;
+
+    /// Handles the command `command` with `request`, and returns the response or the status word
+    /// of the error.
+    ///
+    /// The RAM of the application is not initialized: global variables and the heap must not be
+    /// used.
+    fn handle(command: u32, request: Self::Request) -> Result<Self::Response, Reply>;
+}
+
+/// Handles the library call whose parameters are pointed to by `arg0`, if it has been made with
+/// [`call`], and returns false otherwise.
+///
+/// Invalid requests are rejected with [`StatusWords::BadLen`], and responses which do not fit in
+/// the buffer of the caller with [`SyscallError::Overflow`].
+pub fn lib_main<P: LibCallProvider>(arg0: u32) -> bool {
+    let args = arg0 as *const LibArgs;
+    let (id, command, parameters) = unsafe { ((*args).id, (*args).command, (*args).parameters) };
+    if id != LIBCALL_ID || parameters.is_null() {
+        return false;
+    }
+
+    let frame = unsafe { &mut *(parameters as *mut CallFrame) };
+    let buffer = unsafe { core::slice::from_raw_parts_mut(frame.data, frame.capacity) };
+    let result = buffer
+        .get(..frame.len)
+        .and_then(P::Request::deserialize)
+        .ok_or(StatusWords::BadLen.into())
+        .and_then(|request| P::handle(command, request))
+        .and_then(|response| {
+            response
+                .serialize(buffer)
+                .ok_or(SyscallError::Overflow.into())
+        });
+    (frame.len, frame.status) = match result {
+        Ok(len) => (len, StatusWords::Ok as u32),
+        Err(reply) => (0, reply.0 as u32),
+    };
+    true
+}
+
+/// Defines `library_main`, the entry point of an application called as a library.
+///
+/// The application is still started normally through `sample_main`, which is left unchanged.
+/// Library calls made with [`call`](crate::libcall::call) are handled by `provider` (see
+/// [`lib_main`](crate::libcall::lib_main)), and the ones made by the Exchange application by
+/// `swap` (see [`swap::lib_main`](crate::swap::lib_main)). Both are optional, and library calls
+/// are ignored by applications which do not define `library_main`.
+///
+/// # Examples
+///
+/// ```
+/// #[no_mangle]
+/// extern "C" fn sample_main() {
+///     let mut comm = Comm::new();
+///     ...
+/// }
+///
+/// libcall_main!(provider: Provider, swap: App);
+/// ```
+#[macro_export]
+macro_rules! libcall_main {
+    (swap: $swap:ty $(,)?) => {
+        $crate::libcall_main!(, swap: $swap);
+    };
+    ($(provider: $provider:ty)? $(, swap: $swap:ty)? $(,)?) => {
+        #[no_mangle]
+        extern "C" fn library_main(arg0: u32) {
+            $(if $crate::libcall::lib_main::<$provider>(arg0) {
+                return;
+            })?
+            $($crate::swap::lib_main::<$swap>(arg0);)?
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    struct Provider;
+
+    impl LibCallProvider for Provider {
+        type Request = u32;
+        type Response = u64;
+
+        fn handle(command: u32, request: u32) -> Result<u64, Reply> {
+            match command {
+                1 => Ok(request as u64 * 2),
+                _ => Err(StatusWords::BadIns.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn libcall_provider() {
+        let mut buffer = [0u8; 8];
+        let len = 0x1234u32.serialize(&mut buffer);
+        assert_eq!(len, Some(4));
+        let mut frame = CallFrame {
+            data: buffer.as_mut_ptr(),
+            len: 4,
+            capacity: buffer.len(),
+            status: 0,
+        };
+        let mut args = LibArgs {
+            id: LIBCALL_ID,
+            command: 1,
+            _unused: 0,
+            parameters: &mut frame as *mut CallFrame as *mut u8,
+        };
+        let arg0 = &mut args as *mut LibArgs as u32;
+
+        assert_eq!(lib_main::<Provider>(arg0), true);
+        assert_eq!(frame.status, 0x9000);
+        assert_eq!(u64::deserialize(&buffer[..frame.len]), Some(0x2468));
+
+        args.command = 2;
+        frame.len = 4;
+        assert_eq!(lib_main::<Provider>(arg0), true);
+        assert_eq!(frame.status, StatusWords::BadIns as u32);
+        assert_eq!(frame.len, 0);
+
+        args.id = 0x100;
+        assert_eq!(lib_main::<Provider>(arg0), false);
+    }
+}
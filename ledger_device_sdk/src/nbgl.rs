@@ -1,3 +1,4 @@
+use crate::executor::now_or_never;
 use crate::io::{ApduHeader, Comm, Event, Reply};
 use crate::nvm::*;
 use const_zero::const_zero;
@@ -5,7 +6,9 @@ extern crate alloc;
 use alloc::ffi::CString;
 use alloc::{vec, vec::Vec};
 use core::ffi::{c_char, c_int};
+use core::future::poll_fn;
 use core::mem::transmute;
+use core::task::Poll;
 use ledger_secure_sdk_sys::*;
 
 #[no_mangle]
@@ -69,6 +72,13 @@ impl From<SyncNbgl> for u8 {
 static mut G_RET: u8 = 0;
 static mut G_ENDED: bool = false;
 
+/// How synchronous NBGL flows wait for the user: blocking, or in a future.
+#[derive(Copy, Clone)]
+enum UxWait {
+    Blocking,
+    Async,
+}
+
 trait SyncNBGL: Sized {
     fn ux_sync_init(&self) {
         unsafe {
@@ -92,6 +102,26 @@ trait SyncNBGL: Sized {
             }
         }
     }
+
+    /// Waits for the end of the flow as a future, polled by an
+    /// [`Executor`](crate::executor::Executor) which processes the touch events.
+    async fn ux_sync_end(&self) -> SyncNbgl {
+        poll_fn(|_| unsafe {
+            if G_ENDED {
+                Poll::Ready(G_RET.into())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    async fn ux_sync_wait_with(&self, wait: UxWait) -> SyncNbgl {
+        match wait {
+            UxWait::Blocking => self.ux_sync_wait(false),
+            UxWait::Async => self.ux_sync_end().await,
+        }
+    }
 }
 
 unsafe extern "C" fn choice_callback(confirm: bool) {
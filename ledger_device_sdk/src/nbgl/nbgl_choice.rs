@@ -53,6 +53,44 @@ impl<'a> NbglChoice<'a> {
         sub_message: &str,
         confirm_text: &str,
         cancel_text: &str,
+    ) -> bool {
+        let choice = self.choice(
+            message,
+            sub_message,
+            confirm_text,
+            cancel_text,
+            UxWait::Blocking,
+        );
+        now_or_never(choice).unwrap_or(false)
+    }
+
+    /// Same as [`NbglChoice::show`], as a future which must be run by an
+    /// [`Executor`](crate::executor::Executor): APDUs keep being processed while the choice is
+    /// displayed.
+    pub async fn show_async(
+        &self,
+        message: &str,
+        sub_message: &str,
+        confirm_text: &str,
+        cancel_text: &str,
+    ) -> bool {
+        self.choice(
+            message,
+            sub_message,
+            confirm_text,
+            cancel_text,
+            UxWait::Async,
+        )
+        .await
+    }
+
+    async fn choice(
+        &self,
+        message: &str,
+        sub_message: &str,
+        confirm_text: &str,
+        cancel_text: &str,
+        wait: UxWait,
     ) -> bool {
         unsafe {
             let icon: nbgl_icon_details_t = match self.glyph {
@@ -79,7 +117,7 @@ impl<'a> NbglChoice<'a> {
                 cancel_text.as_ptr() as *const c_char,
                 Some(choice_callback),
             );
-            let sync_ret = self.ux_sync_wait(false);
+            let sync_ret = self.ux_sync_wait_with(wait).await;
 
             // Return true if the user approved the transaction, false otherwise.
             match sync_ret {
@@ -67,6 +67,17 @@ impl<'a> NbglReview<'a> {
     }
 
     pub fn show(&self, fields: &[Field]) -> bool {
+        now_or_never(self.review(fields, UxWait::Blocking)).unwrap_or(false)
+    }
+
+    /// Same as [`NbglReview::show`], as a future which must be run by an
+    /// [`Executor`](crate::executor::Executor): APDUs keep being processed while the review is
+    /// displayed.
+    pub async fn show_async(&self, fields: &[Field<'_>]) -> bool {
+        self.review(fields, UxWait::Async).await
+    }
+
+    async fn review(&self, fields: &[Field<'_>], wait: UxWait) -> bool {
         unsafe {
             let v: Vec<CField> = fields
                 .iter()
@@ -138,7 +149,7 @@ impl<'a> NbglReview<'a> {
                     }
                 }
             }
-            let sync_ret = self.ux_sync_wait(false);
+            let sync_ret = self.ux_sync_wait_with(wait).await;
 
             // Return true if the user approved the transaction, false otherwise.
             match sync_ret {
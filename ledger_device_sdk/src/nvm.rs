@@ -50,6 +50,15 @@ use AtomicStorageElem::{StorageA, StorageB};
 /// Returned when trying to insert data when no more space is available
 pub struct StorageFullError;
 
+/// Returned when reading a value which cannot be trusted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StorageError {
+    /// The last update of the value has been interrupted
+    Invalid,
+    /// The value does not match its checksum
+    Corrupted,
+}
+
 /// What storage of single element should implement
 ///
 /// The address of the stored object, returned with get_ref, MUST remain the
@@ -148,6 +157,16 @@ impl<T> SafeStorage<T> {
     pub fn is_valid(&self) -> bool {
         *self.flag.get_ref() == STORAGE_VALID
     }
+
+    /// Return non-mutable reference to the stored value, or an error if the
+    /// storage is not valid (corrupted).
+    pub fn try_get_ref(&self) -> Result<&T, StorageError> {
+        if self.is_valid() {
+            Ok(self.value.get_ref())
+        } else {
+            Err(StorageError::Invalid)
+        }
+    }
 }
 
 impl<T> SingleStorage<T> for SafeStorage<T> {
@@ -188,9 +207,10 @@ macro_rules! atomic_storage {
 atomic_storage!(64);
 #[cfg(target_os = "nanox")]
 atomic_storage!(256);
-#[cfg(any(target_os = "nanosplus", target_os = "stax", target_os = "flex"))]
+#[cfg(not(any(target_os = "nanos", target_os = "nanox")))]
 atomic_storage!(512);
 
+#[derive(Copy, Clone)]
 pub enum AtomicStorageElem {
     StorageA,
     StorageB,
@@ -215,12 +235,46 @@ where
     /// Panics if both storage elements are invalid (data corrupton),
     /// although data corruption shall not be possible with tearing.
     fn which(&self) -> AtomicStorageElem {
+        match self.find_valid() {
+            Some(elem) => elem,
+            None => panic!("invalidated atomic storage"),
+        }
+    }
+
+    /// Returns which storage contains the latest valid data, or None if both
+    /// storage elements are invalid.
+    fn find_valid(&self) -> Option<AtomicStorageElem> {
         if self.storage_a.is_valid() {
-            StorageA
+            Some(StorageA)
         } else if self.storage_b.is_valid() {
-            StorageB
+            Some(StorageB)
         } else {
-            panic!("invalidated atomic storage");
+            None
+        }
+    }
+
+    /// Returns true if one of the storage elements holds valid data.
+    pub fn is_valid(&self) -> bool {
+        self.find_valid().is_some()
+    }
+
+    /// Return reference to the stored value, or an error if both storage
+    /// elements are invalid.
+    pub fn try_get_ref(&self) -> Result<&T, StorageError> {
+        match self.find_valid() {
+            Some(StorageA) => self.storage_a.try_get_ref(),
+            Some(StorageB) => self.storage_b.try_get_ref(),
+            None => Err(StorageError::Invalid),
+        }
+    }
+
+    /// Overwrites the stored value, even if both storage elements are
+    /// invalid. This is meant to recover from data corruption.
+    pub fn reset(&mut self, value: &T) {
+        match self.find_valid() {
+            Some(_) => self.update(value),
+            // If interrupted, the storage remains invalid and can be reset again
+            None => self.storage_a.update(value),
         }
     }
 }
@@ -252,20 +306,539 @@ where
         }
     }
 }
+
+/// Continues the computation of a CRC-32 (IEEE 802.3) with `bytes`. The CRC
+/// of a message is `!crc32_update(0xffff_ffff, message)`.
+const fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    let mut i = 0;
+    while i < bytes.len() {
+        crc ^= bytes[i] as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+            bit += 1;
+        }
+        i += 1;
+    }
+    crc
+}
+
+/// What a [`ResilientStorage`] does when its value cannot be trusted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Reset the value to its default value.
+    #[default]
+    ResetToDefault,
+    /// Restore the previous copy of the value if it matches its checksum,
+    /// otherwise reset the value to its default value. This requires the CRC
+    /// to be enabled.
+    KeepLastGood,
+}
+
+/// Value of a [`CheckedStorage`], with the sequence number of its update and
+/// its checksum.
+#[derive(Copy, Clone)]
+struct Checked<T> {
+    value: T,
+    sequence: u32,
+    crc: u32,
+}
+
+impl<T> Checked<T>
+where
+    T: Copy,
+{
+    const fn new(value: &T, sequence: u32, crc: bool) -> Checked<T> {
+        Checked {
+            value: *value,
+            sequence,
+            crc: if crc {
+                Self::checksum(value, sequence)
+            } else {
+                0
+            },
+        }
+    }
+
+    const fn checksum(value: &T, sequence: u32) -> u32 {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+        };
+        let crc = crc32_update(0xffff_ffff, bytes);
+        !crc32_update(crc, &sequence.to_be_bytes())
+    }
+
+    fn is_intact(&self) -> bool {
+        self.crc == Self::checksum(&self.value, self.sequence)
+    }
+}
+
+/// Non-Volatile data storage with atomic update support, which detects data
+/// corruption and recovers from it. This is the layer shared by
+/// [`ResilientStorage`] and [`VersionedStorage`].
+///
+/// Each update is numbered, so that the newest of the two copies kept by the
+/// [`AtomicStorage`] can be found even when their validity flags cannot be
+/// trusted, and optionally protected by a CRC.
+struct CheckedStorage<T> {
+    storage: AtomicStorage<Checked<T>>,
+    crc: bool,
+}
+
+impl<T> CheckedStorage<T>
+where
+    T: Copy,
+{
+    const fn new(value: &T, crc: bool) -> CheckedStorage<T> {
+        CheckedStorage {
+            storage: AtomicStorage::new(&Checked::new(value, 0, crc)),
+            crc,
+        }
+    }
+
+    /// Returns the value given to [`CheckedStorage::new`], for builders
+    /// rebuilding the storage before it is written.
+    const fn initial(&self) -> T {
+        self.storage.storage_a.value.value.value
+    }
+
+    /// Returns the storage element `elem`.
+    fn copy(&self, elem: AtomicStorageElem) -> &SafeStorage<Checked<T>> {
+        match elem {
+            StorageA => &self.storage.storage_a,
+            StorageB => &self.storage.storage_b,
+        }
+    }
+
+    /// Returns which of the storage elements accepted by `accept` holds the
+    /// newest copy.
+    fn newest(
+        &self,
+        accept: impl Fn(&SafeStorage<Checked<T>>) -> bool,
+    ) -> Option<AtomicStorageElem> {
+        // The last maximum is returned, so that A is the newest on ties, as
+        // with an AtomicStorage
+        [StorageB, StorageA]
+            .into_iter()
+            .filter(|elem| accept(self.copy(*elem)))
+            .max_by_key(|elem| self.copy(*elem).value.get_ref().sequence)
+    }
+
+    /// Returns which storage element holds the newest copy which can be
+    /// restored, whatever its flag.
+    fn last_good(&self) -> Option<AtomicStorageElem> {
+        self.newest(|storage| self.crc && storage.value.get_ref().is_intact())
+    }
+
+    /// Returns true if `checked` matches its checksum, or if the CRC is
+    /// disabled.
+    fn is_intact(&self, checked: &Checked<T>) -> bool {
+        !self.crc || checked.is_intact()
+    }
+
+    /// Return reference to the stored value, or an error if it cannot be
+    /// trusted.
+    fn try_get_ref(&self) -> Result<&T, StorageError> {
+        let elem = self
+            .newest(|storage| storage.is_valid())
+            .ok_or(StorageError::Invalid)?;
+        let checked = self.copy(elem).value.get_ref();
+        if !self.is_intact(checked) {
+            return Err(StorageError::Corrupted);
+        }
+        Ok(&checked.value)
+    }
+
+    /// Return reference to the stored value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stored value cannot be trusted.
+    fn get_ref(&self) -> &T {
+        match self.try_get_ref() {
+            Ok(value) => value,
+            Err(_) => panic!("corrupted storage"),
+        }
+    }
+
+    /// Update the value by writting to the NVM memory, even if the stored
+    /// value cannot be trusted.
+    ///
+    /// The value is written to the storage element which does not hold the
+    /// current copy, or the last good one, and that copy is invalidated
+    /// afterwards. If interrupted, the previous value is thus kept.
+    fn update(&mut self, value: &T) {
+        let kept = self
+            .newest(|storage| storage.is_valid() && self.is_intact(storage.value.get_ref()))
+            .or_else(|| self.last_good());
+        let sequence = kept.map_or(0, |elem| {
+            self.copy(elem).value.get_ref().sequence.wrapping_add(1)
+        });
+        let checked = Checked::new(value, sequence, self.crc);
+        match kept {
+            Some(StorageA) => {
+                self.storage.storage_b.update(&checked);
+                self.storage.storage_a.invalidate();
+            }
+            _ => {
+                self.storage.storage_a.update(&checked);
+                self.storage.storage_b.invalidate();
+            }
+        }
+    }
+
+    /// Recovers the stored value according to `policy` if it cannot be
+    /// trusted, falling back to `default`. Returns the error which has been
+    /// recovered from, if any.
+    fn recover(&mut self, policy: RecoveryPolicy, default: &T) -> Option<StorageError> {
+        let error = self.try_get_ref().err()?;
+        // Copies left by interrupted updates are restored if they are complete
+        let last_good = self
+            .last_good()
+            .map(|elem| self.copy(elem).value.get_ref().value);
+        match (policy, last_good) {
+            (RecoveryPolicy::KeepLastGood, Some(value)) => self.update(&value),
+            _ => self.update(default),
+        }
+        Some(error)
+    }
+}
+
+/// Non-Volatile data storage with atomic update support, which recovers from
+/// data corruption instead of panicking.
+///
+/// The value can be protected by a CRC, enabled with [`set_crc`], to detect
+/// corruption which is not caused by an interrupted update. When the value
+/// cannot be trusted, it is recovered according to the [`RecoveryPolicy`] set
+/// with [`set_recovery_policy`].
+///
+/// With the CRC enabled, `T` must not contain padding bytes nor pointers.
+///
+/// # Examples
+///
+/// ```
+/// #[link_section=".nvm_data"]
+/// static mut SETTINGS: NVMData<ResilientStorage<Settings>> = NVMData::new(
+///     ResilientStorage::new(&Settings::DEFAULT)
+///         .set_crc(true)
+///         .set_recovery_policy(RecoveryPolicy::KeepLastGood),
+/// );
+/// ```
+///
+/// [`set_crc`]: ResilientStorage::set_crc
+/// [`set_recovery_policy`]: ResilientStorage::set_recovery_policy
+pub struct ResilientStorage<T> {
+    storage: CheckedStorage<T>,
+    policy: RecoveryPolicy,
+}
+
+impl<T> ResilientStorage<T>
+where
+    T: Copy + Default,
+{
+    /// Create a ResilientStorage<T> initialized with a given value, without
+    /// CRC and resetting to the default value on corruption.
+    pub const fn new(value: &T) -> ResilientStorage<T> {
+        ResilientStorage {
+            storage: CheckedStorage::new(value, false),
+            policy: RecoveryPolicy::ResetToDefault,
+        }
+    }
+
+    /// Enables or disables the CRC of the stored value.
+    pub const fn set_crc(mut self, crc: bool) -> Self {
+        self.storage = CheckedStorage::new(&self.storage.initial(), crc);
+        self
+    }
+
+    /// Sets the recovery policy applied when the stored value cannot be trusted.
+    pub const fn set_recovery_policy(mut self, policy: RecoveryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Return reference to the stored value, or an error if it cannot be
+    /// trusted.
+    pub fn try_get_ref(&self) -> Result<&T, StorageError> {
+        self.storage.try_get_ref()
+    }
+
+    /// Returns a reference to the stored value, after recovering it if it
+    /// cannot be trusted.
+    pub fn get(&mut self) -> &T {
+        self.recover();
+        self.storage.get_ref()
+    }
+
+    /// Update the value by writting to the NVM memory.
+    pub fn update(&mut self, value: &T) {
+        self.storage.update(value);
+    }
+
+    /// Recovers the stored value according to the recovery policy if it cannot
+    /// be trusted. Returns the error which has been recovered from, if any.
+    ///
+    /// With [`RecoveryPolicy::KeepLastGood`], the newest copy of the value
+    /// which matches its checksum is restored.
+    pub fn recover(&mut self) -> Option<StorageError> {
+        self.storage.recover(self.policy, &T::default())
+    }
+}
+
+/// Magic marking the data of a [`VersionedStorage`].
+const VERSIONED_MAGIC: u32 = 0x5645_5253;
+
+/// Returned by a [`Migration`] which cannot upgrade the stored data.
+pub struct MigrationError;
+
+/// Upgrades in place the bytes of a value stored with a [`VersionedStorage`] to
+/// the next schema version.
+///
+/// Once migrated, the bytes are read as a value of the current type, without
+/// any check (see the safety requirements of [`Versioned`]).
+pub type Migration = fn(&mut [u8]) -> Result<(), MigrationError>;
+
+/// Value stored in a [`VersionedStorage`], with its schema version and the
+/// migrations from its previous layouts.
+///
+/// # Safety
+///
+/// Once migrated, the stored bytes are read as a value of `Self` without any
+/// check. The migrations must thus turn the data of the versions they upgrade
+/// into a valid value of `Self`: a valid value for each of its fields, such as
+/// 0 or 1 for a `bool`, or a declared discriminant for an enum. Fields of
+/// types for which any bit pattern is valid (integers, byte arrays) do not
+/// require such care. `Self` must not contain references nor pointers.
+///
+/// # Examples
+///
+/// ```
+/// #[derive(Copy, Clone, Default)]
+/// #[repr(C)]
+/// struct Settings {
+///     blind_signing: bool,
+///     // Added in version 1
+///     display_hash: bool,
+/// }
+///
+/// // Safety: the migration writes a valid `bool`
+/// unsafe impl Versioned for Settings {
+///     const VERSION: u16 = 1;
+///     const MIGRATIONS: &'static [Migration] = &[|data| {
+///         data[1] = 0;
+///         Ok(())
+///     }];
+/// }
+/// ```
+pub unsafe trait Versioned: Copy + Default {
+    /// Version of the current layout of the type.
+    const VERSION: u16;
+    /// Migrations from the previous versions: the last one upgrades data from
+    /// `VERSION - 1` to `VERSION`, the one before from `VERSION - 2` to
+    /// `VERSION - 1`, etc. Data stored with a version which cannot be upgraded
+    /// is reset to the default value.
+    ///
+    /// The migrated bytes must be a valid value of `Self` (see the safety
+    /// requirements of the trait).
+    const MIGRATIONS: &'static [Migration] = &[];
+}
+
+/// Stored value of a [`VersionedStorage`], as raw bytes for migrations.
+/// The alignment keeps the offset of the value independent of its type.
+#[repr(C, align(8))]
+#[derive(Copy, Clone)]
+union VersionedPayload<T: Copy, const SIZE: usize> {
+    value: T,
+    bytes: [u8; SIZE],
+}
+
+/// Data of a [`VersionedStorage`]. It has no padding bytes, which would be
+/// read by the CRC.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VersionedData<T: Copy, const SIZE: usize> {
+    magic: u32,
+    version: u16,
+    reserved: u16,
+    payload: VersionedPayload<T, SIZE>,
+}
+
+/// Non-Volatile data storage with atomic update support, which keeps the
+/// stored data across changes of its layout.
+///
+/// The value is stored in `SIZE` bytes along with the schema version and a
+/// magic. On first access after an application upgrade, the data stored by the
+/// previous version is upgraded with [`Versioned::MIGRATIONS`]. If the data has
+/// not been written by a `VersionedStorage`, or cannot be migrated, it is reset
+/// to the default value instead.
+///
+/// As with [`ResilientStorage`], the data can be protected by a CRC, enabled
+/// with [`set_crc`], and is recovered according to the [`RecoveryPolicy`] set
+/// with [`set_recovery_policy`] when it cannot be trusted, before being
+/// upgraded.
+///
+/// `SIZE` must not change across versions, so it should leave room for future
+/// fields. It must be a multiple of 8.
+///
+/// With the CRC enabled, `T` must not contain padding bytes nor pointers.
+///
+/// [`set_crc`]: VersionedStorage::set_crc
+/// [`set_recovery_policy`]: VersionedStorage::set_recovery_policy
+pub struct VersionedStorage<T: Versioned, const SIZE: usize> {
+    storage: CheckedStorage<VersionedData<T, SIZE>>,
+    policy: RecoveryPolicy,
+}
+
+impl<T, const SIZE: usize> VersionedStorage<T, SIZE>
+where
+    T: Versioned,
+{
+    /// Fails to compile if `T` does not fit in `SIZE` bytes, or if the payload
+    /// would be followed by padding bytes.
+    const SIZE_CHECK: () = {
+        assert!(core::mem::size_of::<T>() <= SIZE, "SIZE is too small");
+        assert!(SIZE.is_multiple_of(8), "SIZE must be a multiple of 8");
+    };
+
+    /// Create a VersionedStorage<T> initialized with a given value, without
+    /// CRC and resetting to the default value on corruption.
+    pub const fn new(value: &T) -> VersionedStorage<T, SIZE> {
+        let () = Self::SIZE_CHECK;
+        VersionedStorage {
+            storage: CheckedStorage::new(&Self::data(value), false),
+            policy: RecoveryPolicy::ResetToDefault,
+        }
+    }
+
+    /// Enables or disables the CRC of the stored data.
+    pub const fn set_crc(mut self, crc: bool) -> Self {
+        self.storage = CheckedStorage::new(&self.storage.initial(), crc);
+        self
+    }
+
+    /// Sets the recovery policy applied when the stored data cannot be trusted.
+    pub const fn set_recovery_policy(mut self, policy: RecoveryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the stored data of `value`, with the current version.
+    const fn data(value: &T) -> VersionedData<T, SIZE> {
+        let mut payload = VersionedPayload { bytes: [0; SIZE] };
+        payload.value = *value;
+        VersionedData {
+            magic: VERSIONED_MAGIC,
+            version: T::VERSION,
+            reserved: 0,
+            payload,
+        }
+    }
+
+    /// Returns a reference to the stored value, after upgrading it or
+    /// resetting it to the default value if required.
+    pub fn get(&mut self) -> &T {
+        self.upgrade();
+        unsafe { &self.storage.get_ref().payload.value }
+    }
+
+    /// Update the value by writting to the NVM memory.
+    pub fn update(&mut self, value: &T) {
+        self.storage.update(&Self::data(value));
+    }
+
+    /// Resets the stored value to the default value.
+    pub fn reset(&mut self) {
+        self.update(&T::default());
+    }
+
+    /// Recovers the stored data if it cannot be trusted, then upgrades it to
+    /// the current version, or resets it to the default value if it is not
+    /// versioned data or cannot be upgraded.
+    fn upgrade(&mut self) {
+        self.storage
+            .recover(self.policy, &Self::data(&T::default()));
+        let mut data = *self.storage.get_ref();
+        if data.magic != VERSIONED_MAGIC || data.version > T::VERSION {
+            return self.reset();
+        }
+        if data.version == T::VERSION {
+            return;
+        }
+        let first = match (T::VERSION as usize).checked_sub(T::MIGRATIONS.len()) {
+            Some(first) if data.version as usize >= first => first,
+            _ => return self.reset(),
+        };
+        let bytes = unsafe { &mut data.payload.bytes };
+        for migration in &T::MIGRATIONS[data.version as usize - first..] {
+            if migration(bytes).is_err() {
+                return self.reset();
+            }
+        }
+        data.version = T::VERSION;
+        self.storage.update(&data);
+    }
+}
+
 pub struct KeyOutOfRange;
 
+/// Magic marking the state of a [`Collection`].
+const COLLECTION_MAGIC: u32 = 0x434f_4c4c;
+
+/// Allocation state of the slots of a [`Collection`], updated atomically.
+#[derive(Copy, Clone)]
+struct CollectionState<const N: usize> {
+    /// Distinguishes the state from data written with another layout
+    magic: u32,
+    /// Allocation flag of each slot
+    flags: [u8; N],
+    /// Keys of the items, in the order of the collection. Only the first `len` are used.
+    keys: [usize; N],
+    /// Number of items
+    len: usize,
+}
+
+impl<const N: usize> CollectionState<N> {
+    const EMPTY: CollectionState<N> = CollectionState {
+        magic: COLLECTION_MAGIC,
+        flags: [0; N],
+        keys: [0; N],
+        len: 0,
+    };
+
+    /// Returns true if the state has been written with this layout, and its keys designate
+    /// allocated slots.
+    fn is_valid(&self) -> bool {
+        self.magic == COLLECTION_MAGIC
+            && self.len <= N
+            && self.keys[..self.len]
+                .iter()
+                .all(|&key| key < N && self.flags[key] == STORAGE_VALID)
+    }
+}
+
 /// A Non-Volatile fixed-size collection of fixed-size items.
-/// Items insertion and deletion are atomic.
-/// Items update is not implemented because the atomicity of this operation
-/// cannot be guaranteed here.
+/// Items insertion, deletion and update are atomic.
+///
+/// Items are indexed in the order they have been added: an item keeps its index when it is
+/// replaced with [`Collection::replace`], and the following items are shifted when it is
+/// removed.
+///
+/// The layout of a `Collection` in Flash changed with the introduction of this order: the data
+/// of a `Collection` written by a previous version of the SDK, or which is not consistent, is
+/// read as an empty collection, and overwritten by the next change. Items used to be indexed in
+/// the order of their slots, which differs from the order in which they have been added once
+/// items have been removed.
 // We use the term `index` to represent the user-facing number of an element in the collection,
 // and the term `key` to represent the underlying offset at which the element is located in the collection.
+// The keys of the items are stored in the order of the collection, so that an item can be
+// replaced by writing the new value in a free slot, without changing its index.
 // e.g with `[0, 0, 1, 1, 0, 1, 0]` (with 0s representing free slots and 1s representing allocated slots)
+// and keys `[5, 2, 3]`:
 //            ↑  ↑  ↑  ↑  ↑  ↑  ↑
-// index:     -  -  0  1  -  2  -
+// index:     -  -  1  2  -  0  -
 // key:       0, 1, 2, 3, 4, 5, 6
 pub struct Collection<T, const N: usize> {
-    flags: AtomicStorage<[u8; N]>,
+    state: AtomicStorage<CollectionState<N>>,
     slots: [AlignedStorage<T>; N],
 }
 
@@ -275,18 +848,30 @@ where
 {
     pub const fn new(value: T) -> Collection<T, N> {
         Collection {
-            flags: AtomicStorage::new(&[0; N]),
+            state: AtomicStorage::new(&CollectionState::EMPTY),
             slots: [AlignedStorage::new(value); N],
         }
     }
 
+    /// Returns the stored allocation state, or None if it has not been written
+    /// with the current layout or is corrupted: the collection is then empty.
+    fn state(&self) -> Option<&CollectionState<N>> {
+        self.state
+            .try_get_ref()
+            .ok()
+            .filter(|state| state.is_valid())
+    }
+
+    /// Returns a copy of the allocation state, to be updated.
+    fn new_state(&self) -> CollectionState<N> {
+        self.state().copied().unwrap_or(CollectionState::EMPTY)
+    }
+
     /// Finds and returns a reference to a free slot, or returns None if
     /// all slots are allocated.
     fn find_free_slot(&self) -> Option<usize> {
-        self.flags
-            .get_ref()
-            .iter()
-            .position(|&e| e != STORAGE_VALID)
+        let flags = self.state().map(|state| &state.flags);
+        (0..N).find(|&i| flags.is_none_or(|flags| flags[i] != STORAGE_VALID))
     }
 
     /// Adds an item in the collection. Returns an error if there is not free
@@ -296,41 +881,54 @@ where
         match self.find_free_slot() {
             Some(i) => {
                 self.slots[i].update(value);
-                let mut new_flags = *self.flags.get_ref();
-                new_flags[i] = STORAGE_VALID;
-                self.flags.update(&new_flags);
+                let mut new_state = self.new_state();
+                new_state.flags[i] = STORAGE_VALID;
+                new_state.keys[new_state.len] = i;
+                new_state.len += 1;
+                self.state.reset(&new_state);
                 Ok(())
             }
             None => Err(StorageFullError),
         }
     }
 
-    /// Returns a boolean representing whether the slot at `key` was allocated or not.
+    /// Replaces the item located at `index` with `value`, without changing its index. Returns an
+    /// error if there is no free slot, as the new value is written in a free slot before being
+    /// swapped with the current one.
+    /// This operation is atomic.
     ///
-    /// # Errors
+    /// # Arguments
     ///
-    /// Returns an error if the `key` is out of range.
-    fn is_allocated(&self, key: usize) -> Result<bool, KeyOutOfRange> {
-        match self.flags.get_ref().get(key) {
-            Some(&byte) => {
-                if byte == STORAGE_VALID {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
+    /// * `index` - Item index
+    /// * `value` - New value of the item
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn replace(&mut self, index: usize, value: &T) -> Result<(), StorageFullError> {
+        let key = self.index_to_key(index).unwrap();
+        match self.find_free_slot() {
+            Some(i) => {
+                self.slots[i].update(value);
+                let mut new_state = self.new_state();
+                new_state.flags[i] = STORAGE_VALID;
+                new_state.flags[key] = 0;
+                new_state.keys[index] = i;
+                self.state.reset(&new_state);
+                Ok(())
             }
-            None => Err(KeyOutOfRange),
+            None => Err(StorageFullError),
         }
     }
 
     /// Returns the number of allocated slots.
     pub fn len(&self) -> usize {
-        self.count_allocated(N)
+        self.state().map_or(0, |state| state.len)
     }
 
     /// Returns true if collection is empty
     pub fn is_empty(&self) -> bool {
-        !self.flags.get_ref().iter().any(|v| *v == STORAGE_VALID)
+        self.len() == 0
     }
 
     /// Returns the maximum number of items the collection can store.
@@ -344,15 +942,6 @@ where
         self.capacity() - self.len()
     }
 
-    /// Counts the number of allocated slots up until `len`.
-    fn count_allocated(&self, len: usize) -> usize {
-        self.flags
-            .get_ref()
-            .iter()
-            .take(len)
-            .fold(0, |acc, &byte| acc + (byte == STORAGE_VALID) as u32) as usize
-    }
-
     /// Returns the `key` of an item in the internal storage, given the `index`
     /// in the collection. If `index` is too big, None is returned.
     ///
@@ -360,22 +949,8 @@ where
     ///
     /// * `index` - Index in the collection
     fn index_to_key(&self, index: usize) -> Option<usize> {
-        // Neat optimization: start by setting `next` to index,
-        // because we know we could not have found `index` allocated slots beforehand.
-        let mut key = index;
-        // Now count the number of allocated slots we have found up
-        // until this `index` (without including the slot at `index` itself).
-        let mut allocated_count = self.count_allocated(index);
-        loop {
-            let is_allocated = self.is_allocated(key).ok()?;
-            if is_allocated {
-                if allocated_count == index {
-                    return Some(key);
-                }
-                allocated_count += 1;
-            }
-            key += 1;
-        }
+        let state = self.state()?;
+        state.keys[..state.len].get(index).copied()
     }
 
     /// Returns reference to an item, or None if the index is out of bounds
@@ -391,6 +966,8 @@ where
     }
 
     /// Removes the item located at `index` from the collection.
+    /// The following items are shifted by one index.
+    /// This operation is atomic.
     ///
     /// # Arguments
     ///
@@ -401,15 +978,17 @@ where
     /// Panics if `index` is out of bounds.
     pub fn remove(&mut self, index: usize) {
         let key = self.index_to_key(index).unwrap();
-        let mut new_flags = *self.flags.get_ref();
-        new_flags[key] = 0;
-        self.flags.update(&new_flags);
+        let mut new_state = self.new_state();
+        new_state.flags[key] = 0;
+        new_state.keys.copy_within(index + 1..new_state.len, index);
+        new_state.len -= 1;
+        self.state.reset(&new_state);
     }
 
     /// Removes all the items from the collection.
     /// This operation is atomic.
     pub fn clear(&mut self) {
-        self.flags.update(&[0; N]);
+        self.state.reset(&CollectionState::EMPTY);
     }
 }
 
@@ -423,7 +1002,7 @@ where
     fn into_iter(self) -> CollectionIterator<'a, T, N> {
         CollectionIterator {
             container: self,
-            next_key: 0,
+            next_index: 0,
         }
     }
 }
@@ -433,7 +1012,7 @@ where
     T: Copy,
 {
     container: &'a Collection<T, N>,
-    next_key: usize,
+    next_index: usize,
 }
 
 impl<'a, T, const N: usize> Iterator for CollectionIterator<'a, T, N>
@@ -443,12 +1022,821 @@ where
     type Item = &'a T;
 
     fn next(&mut self) -> core::option::Option<&'a T> {
-        loop {
-            let is_allocated = self.container.is_allocated(self.next_key).ok()?;
-            self.next_key += 1;
-            if is_allocated {
-                return Some(self.container.slots[self.next_key - 1].get_ref());
+        let item = self.container.get(self.next_index)?;
+        self.next_index += 1;
+        Some(item)
+    }
+}
+
+/// Flash page of a [`KvStore`].
+/// Aligning to the page size is done through a macro as `#[repr(align(N))]` does not accept
+/// variable 'N'.
+macro_rules! kv_page {
+    ($n:expr) => {
+        /// Size of the pages of a [`KvStore`].
+        pub const KV_PAGE_SIZE: usize = $n;
+
+        #[repr(align($n))]
+        #[derive(Copy, Clone)]
+        struct KvPage {
+            data: [u8; $n],
+        }
+    };
+}
+
+#[cfg(target_os = "nanos")]
+kv_page!(64);
+#[cfg(target_os = "nanox")]
+kv_page!(256);
+#[cfg(not(any(target_os = "nanos", target_os = "nanox")))]
+kv_page!(512);
+
+/// Page header: state, then big-endian sequence number.
+const KV_PAGE_HEADER_LEN: usize = 5;
+/// Record header: state, key length, then big-endian value length.
+const KV_RECORD_HEADER_LEN: usize = 4;
+/// State of free pages, and of the free space following the records of a page.
+const KV_FREE: u8 = 0;
+/// State of deleted or replaced records.
+const KV_DELETED: u8 = 0x5a;
+
+/// Record of a [`KvStore`], located at `offset` in `page`.
+struct KvRecord<'a> {
+    page: usize,
+    offset: usize,
+    state: u8,
+    key: &'a [u8],
+    value: &'a [u8],
+}
+
+impl KvRecord<'_> {
+    fn len(&self) -> usize {
+        KV_RECORD_HEADER_LEN + self.key.len() + self.value.len()
+    }
+}
+
+/// Serializes a record of `key` and `value` in `buffer`, with a free state, and returns it.
+/// The caller checks that the record fits in a page.
+fn kv_encode<'a>(buffer: &'a mut [u8; KV_PAGE_SIZE], key: &[u8], value: &[u8]) -> &'a [u8] {
+    let key_start = KV_RECORD_HEADER_LEN;
+    let value_start = key_start + key.len();
+    let len = value_start + value.len();
+    buffer[0] = KV_FREE;
+    buffer[1] = key.len() as u8;
+    buffer[2..key_start].copy_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer[key_start..value_start].copy_from_slice(key);
+    buffer[value_start..len].copy_from_slice(value);
+    &buffer[..len]
+}
+
+/// Parses the record at `offset` in `page`, or returns None if the records of the page end
+/// there.
+fn kv_record(page: usize, data: &[u8; KV_PAGE_SIZE], offset: usize) -> Option<KvRecord<'_>> {
+    let header = data.get(offset..offset + KV_RECORD_HEADER_LEN)?;
+    let state = header[0];
+    if state != STORAGE_VALID && state != KV_DELETED {
+        return None;
+    }
+    let key_len = header[1] as usize;
+    let value_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let key_start = offset + KV_RECORD_HEADER_LEN;
+    let value_start = key_start + key_len;
+    Some(KvRecord {
+        page,
+        offset,
+        state,
+        key: data.get(key_start..value_start)?,
+        value: data.get(value_start..value_start + value_len)?,
+    })
+}
+
+/// A Non-Volatile key-value store, with byte-string keys (up to 255 bytes) and
+/// variable-length values, spanning `PAGES` Flash pages.
+///
+/// Entries are appended as records to the pages, which are used in turn to spread the wear of
+/// the Flash. Records are written before their state is set, so that an interrupted write is
+/// ignored and later overwritten: [`KvStore::put`] and [`KvStore::delete`] are atomic. When the
+/// pages are full, the oldest one is compacted by copying its live entries to a free page,
+/// which is kept in reserve for this purpose: `PAGES` must be at least 2.
+///
+/// # Examples
+///
+/// ```
+/// #[link_section = ".nvm_data"]
+/// static mut ADDRESS_BOOK: NVMData<KvStore<4>> = NVMData::new(KvStore::new());
+///
+/// let book = unsafe { ADDRESS_BOOK.get_mut() };
+/// book.put(b"alice", &address)?;
+/// let address = book.get(b"alice");
+/// ```
+pub struct KvStore<const PAGES: usize> {
+    pages: [KvPage; PAGES],
+}
+
+impl<const PAGES: usize> Default for KvStore<PAGES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGES: usize> KvStore<PAGES> {
+    /// Largest record which can be stored in a page.
+    const MAX_RECORD_LEN: usize = KV_PAGE_SIZE - KV_PAGE_HEADER_LEN;
+
+    /// Fails to compile if there is no page left once one is kept for compaction.
+    const PAGES_CHECK: () = assert!(PAGES >= 2, "PAGES must be at least 2");
+
+    pub const fn new() -> KvStore<PAGES> {
+        let () = Self::PAGES_CHECK;
+        KvStore {
+            pages: [KvPage {
+                data: [0; KV_PAGE_SIZE],
+            }; PAGES],
+        }
+    }
+
+    /// Returns the value stored for `key`, if any.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.latest(key).map(|record| record.value)
+    }
+
+    /// Returns true if a value is stored for `key`.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.latest(key).is_some()
+    }
+
+    /// Returns the number of entries in the store.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns true if the store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Returns an iterator over the keys and values of the store.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.records()
+            .filter(|record| self.is_latest(record))
+            .map(|record| (record.key, record.value))
+    }
+
+    /// Stores `value` for `key`, replacing the previous value if any. This operation is atomic.
+    ///
+    /// Returns an error if there is not enough space left, even after compaction, or if the key
+    /// is longer than 255 bytes or the entry does not fit in a page. The previous value is only
+    /// deleted once the new one has been written, so replacing a value requires room for both.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageFullError> {
+        let len = KV_RECORD_HEADER_LEN + key.len() + value.len();
+        if key.len() > u8::MAX as usize || len > Self::MAX_RECORD_LEN {
+            return Err(StorageFullError);
+        }
+        // Pages are not compacted in vain if the record would not fit
+        if self.pages_needed(len) > PAGES - 1 {
+            return Err(StorageFullError);
+        }
+
+        let mut buffer = [0u8; KV_PAGE_SIZE];
+        let record = kv_encode(&mut buffer, key, value);
+        // Each compaction frees the oldest page, at most all of them are compacted
+        let mut compactions = 0;
+        while self.append(record, false).is_err() {
+            if compactions == PAGES {
+                return Err(StorageFullError);
+            }
+            self.compact_oldest()?;
+            compactions += 1;
+        }
+
+        // The new record is the latest one: previous ones can be deleted
+        let new = self.latest(key).map(|record| (record.page, record.offset));
+        while let Some((page, offset)) = self.find_valid(key, new) {
+            self.set_state(page, offset, KV_DELETED);
+        }
+        Ok(())
+    }
+
+    /// Deletes the value stored for `key`, and returns true if there was one. This operation is
+    /// atomic.
+    pub fn delete(&mut self, key: &[u8]) -> bool {
+        let mut deleted = false;
+        while let Some((page, offset)) = self.find_valid(key, None) {
+            self.set_state(page, offset, KV_DELETED);
+            deleted = true;
+        }
+        deleted
+    }
+
+    /// Deletes all the entries of the store.
+    /// If this operation is interrupted, only the oldest entries may have been deleted.
+    pub fn clear(&mut self) {
+        // Pages are freed from the oldest one, so that the used pages remain contiguous
+        while let Some(page) = self.oldest() {
+            self.write(page, 0, &[KV_FREE]);
+        }
+    }
+
+    /// Compacts all the pages, to reclaim the space used by deleted or replaced entries.
+    pub fn compact(&mut self) -> Result<(), StorageFullError> {
+        let used = (0..PAGES).filter(|&p| self.sequence(p).is_some()).count();
+        for _ in 0..used {
+            self.compact_oldest()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the sequence number of `page`, or None if the page is free.
+    fn sequence(&self, page: usize) -> Option<u32> {
+        let data = &self.pages[page].data;
+        (data[0] == STORAGE_VALID).then(|| u32::from_be_bytes([data[1], data[2], data[3], data[4]]))
+    }
+
+    /// Returns the page to which records are appended.
+    fn head(&self) -> Option<usize> {
+        (0..PAGES)
+            .filter_map(|page| self.sequence(page).map(|seq| (seq, page)))
+            .max()
+            .map(|(_, page)| page)
+    }
+
+    /// Returns the page holding the oldest records.
+    fn oldest(&self) -> Option<usize> {
+        (0..PAGES)
+            .filter_map(|page| self.sequence(page).map(|seq| (seq, page)))
+            .min()
+            .map(|(_, page)| page)
+    }
+
+    /// Returns the records of the used pages, from the oldest to the latest one.
+    fn records(&self) -> impl Iterator<Item = KvRecord<'_>> {
+        let oldest = self.oldest().unwrap_or(0);
+        let used = (0..PAGES).filter(|&p| self.sequence(p).is_some()).count();
+        (0..used)
+            .map(move |i| (oldest + i) % PAGES)
+            .flat_map(move |page| self.page_records(page))
+    }
+
+    fn page_records(&self, page: usize) -> impl Iterator<Item = KvRecord<'_>> {
+        let data = &self.pages[page].data;
+        let mut offset = KV_PAGE_HEADER_LEN;
+        core::iter::from_fn(move || {
+            let record = kv_record(page, data, offset)?;
+            offset += record.len();
+            Some(record)
+        })
+    }
+
+    /// Returns the latest valid record of `key`.
+    fn latest(&self, key: &[u8]) -> Option<KvRecord<'_>> {
+        self.records()
+            .filter(|r| r.state == STORAGE_VALID && r.key == key)
+            .last()
+    }
+
+    /// Returns the location of the first valid record of `key`, other than `except`.
+    fn find_valid(&self, key: &[u8], except: Option<(usize, usize)>) -> Option<(usize, usize)> {
+        self.records()
+            .filter(|r| r.state == STORAGE_VALID && r.key == key)
+            .map(|r| (r.page, r.offset))
+            .find(|&location| Some(location) != except)
+    }
+
+    /// Returns true if `record` is the latest valid record of its key.
+    fn is_latest(&self, record: &KvRecord) -> bool {
+        record.state == STORAGE_VALID
+            && self
+                .latest(record.key)
+                .is_some_and(|r| (r.page, r.offset) == (record.page, record.offset))
+    }
+
+    /// Returns the number of pages holding the latest valid records, followed by a new record of
+    /// `len` bytes, once all the pages are compacted.
+    fn pages_needed(&self, len: usize) -> usize {
+        let mut pages = 1;
+        let mut used = 0;
+        let live = self
+            .records()
+            .filter(|record| self.is_latest(record))
+            .map(|record| record.len());
+        for record_len in live.chain(core::iter::once(len)) {
+            if used + record_len > Self::MAX_RECORD_LEN {
+                pages += 1;
+                used = 0;
+            }
+            used += record_len;
+        }
+        pages
+    }
+
+    /// Appends a serialized record, whose state is ignored, to the head page, or to the next page
+    /// if it does not fit. The last free page is kept for compaction, unless `reserve` is true.
+    fn append(&mut self, record: &[u8], reserve: bool) -> Result<(), StorageFullError> {
+        let len = record.len();
+        let head = self.head();
+        let end = head.map(|page| {
+            self.page_records(page)
+                .last()
+                .map_or(KV_PAGE_HEADER_LEN, |r| r.offset + r.len())
+        });
+        let (page, offset) = match (head, end) {
+            (Some(page), Some(end)) if end + len <= KV_PAGE_SIZE => (page, end),
+            _ => {
+                let free = (0..PAGES).filter(|&p| self.sequence(p).is_none()).count();
+                if free == 0 || (free == 1 && !reserve) {
+                    return Err(StorageFullError);
+                }
+                self.open_next()
+            }
+        };
+
+        // The records of the page end after this one even if it is shorter than the remains of
+        // an interrupted write, which could otherwise be parsed as records
+        if offset + len < KV_PAGE_SIZE {
+            self.set_state(page, offset + len, KV_FREE);
+        }
+        // The record is valid once its state is written
+        self.write(page, offset + 1, &record[1..]);
+        self.set_state(page, offset, STORAGE_VALID);
+        Ok(())
+    }
+
+    /// Erases the page following the head page, and makes it the new head page. Returns the
+    /// location of its first record.
+    fn open_next(&mut self) -> (usize, usize) {
+        let (page, seq) = match self.head() {
+            Some(head) => ((head + 1) % PAGES, self.sequence(head).unwrap_or(0) + 1),
+            None => (0, 1),
+        };
+        let mut data = [0u8; KV_PAGE_SIZE];
+        data[1..KV_PAGE_HEADER_LEN].copy_from_slice(&seq.to_be_bytes());
+        self.write(page, 0, &data);
+        self.write(page, 0, &[STORAGE_VALID]);
+        (page, KV_PAGE_HEADER_LEN)
+    }
+
+    /// Copies the live records of the oldest page to the head page, then frees the oldest page.
+    fn compact_oldest(&mut self) -> Result<(), StorageFullError> {
+        let oldest = match self.oldest() {
+            Some(page) => page,
+            None => return Ok(()),
+        };
+        if self.head() == Some(oldest) {
+            self.open_next();
+        }
+
+        let mut offset = KV_PAGE_HEADER_LEN;
+        let mut buffer = [0u8; KV_PAGE_SIZE];
+        while let Some(record) = kv_record(oldest, &self.pages[oldest].data, offset) {
+            offset += record.len();
+            if !self.is_latest(&record) {
+                continue;
             }
+            // The record is copied out of the Flash, which is written by `append`
+            let len = record.len();
+            buffer[..len].copy_from_slice(&self.pages[oldest].data[record.offset..offset]);
+            self.append(&buffer[..len], true)?;
+        }
+        self.write(oldest, 0, &[KV_FREE]);
+        Ok(())
+    }
+
+    fn set_state(&mut self, page: usize, offset: usize, state: u8) {
+        self.write(page, offset, &[state]);
+    }
+
+    /// Writes `data` at `offset` in `page`.
+    fn write(&mut self, page: usize, offset: usize, data: &[u8]) {
+        let dst = &mut self.pages[page].data[offset..offset + data.len()];
+        unsafe {
+            nvm_write(
+                dst.as_mut_ptr() as *mut core::ffi::c_void,
+                data.as_ptr() as *mut core::ffi::c_void,
+                data.len() as u32,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[repr(C)]
+    struct Settings {
+        blind_signing: bool,
+        // Added in version 1
+        display_hash: bool,
+        // Added in version 2
+        timeout: u16,
+    }
+
+    // Safety: the migrations write valid `bool` values
+    unsafe impl Versioned for Settings {
+        const VERSION: u16 = 2;
+        const MIGRATIONS: &'static [Migration] = &[
+            |data| {
+                data[1] = 1;
+                Ok(())
+            },
+            |data| {
+                if data[1] > 1 {
+                    return Err(MigrationError);
+                }
+                data[2..4].copy_from_slice(&30u16.to_ne_bytes());
+                Ok(())
+            },
+        ];
+    }
+
+    /// Stores `bytes` as the data of `version`, as another version of the application would.
+    fn store_versioned(
+        storage: &mut VersionedStorage<Settings, 8>,
+        magic: u32,
+        version: u16,
+        bytes: [u8; 8],
+    ) {
+        storage.storage.update(&VersionedData {
+            magic,
+            version,
+            reserved: 0,
+            payload: VersionedPayload { bytes },
+        });
+    }
+
+    #[test]
+    fn versioned_migration() {
+        let default = Settings::default();
+        let mut storage = VersionedStorage::<Settings, 8>::new(&default);
+        store_versioned(
+            &mut storage,
+            VERSIONED_MAGIC,
+            0,
+            [1, 0xff, 0xff, 0xff, 0, 0, 0, 0],
+        );
+
+        let expected = Settings {
+            blind_signing: true,
+            display_hash: true,
+            timeout: 30,
+        };
+        assert_eq!(storage.get(), &expected);
+        assert_eq!(storage.storage.get_ref().version, 2);
+        assert_eq!(storage.get(), &expected);
+    }
+
+    #[test]
+    fn versioned_reset() {
+        let default = Settings::default();
+        let stored = Settings {
+            blind_signing: true,
+            display_hash: true,
+            timeout: 60,
+        };
+        let mut storage = VersionedStorage::<Settings, 8>::new(&stored);
+        assert_eq!(storage.get(), &stored);
+
+        // Newer version
+        let bytes = unsafe { storage.storage.get_ref().payload.bytes };
+        store_versioned(&mut storage, VERSIONED_MAGIC, 3, bytes);
+        assert_eq!(storage.get(), &default);
+
+        // Failing migration
+        store_versioned(&mut storage, VERSIONED_MAGIC, 1, [1, 2, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(storage.get(), &default);
+
+        // Bad magic
+        storage.update(&stored);
+        store_versioned(&mut storage, 0x1234_5678, 2, bytes);
+        assert_eq!(storage.get(), &default);
+    }
+
+    /// Returns a storage in which the current copy, B, holds 3 and the previous one, A, holds 2.
+    fn resilient_storage(policy: RecoveryPolicy) -> ResilientStorage<u32> {
+        let mut storage = ResilientStorage::new(&0)
+            .set_crc(true)
+            .set_recovery_policy(policy);
+        storage.update(&1);
+        storage.update(&2);
+        storage.update(&3);
+        storage
+    }
+
+    #[test]
+    fn resilient_try_get_ref() {
+        let mut storage = ResilientStorage::<u32>::new(&7);
+        assert_eq!(storage.try_get_ref(), Ok(&7));
+        storage.update(&8);
+        assert_eq!(storage.try_get_ref(), Ok(&8));
+
+        // No copy marked as valid
+        let atomic = &mut storage.storage.storage;
+        atomic.storage_a.flag.value = 0;
+        atomic.storage_b.flag.value = 0;
+        assert_eq!(storage.try_get_ref(), Err(StorageError::Invalid));
+    }
+
+    #[test]
+    fn resilient_crc_mismatch() {
+        let mut storage = resilient_storage(RecoveryPolicy::ResetToDefault);
+        assert_eq!(storage.try_get_ref(), Ok(&3));
+
+        storage.storage.storage.storage_b.value.value.value ^= 0x100;
+        assert_eq!(storage.try_get_ref(), Err(StorageError::Corrupted));
+
+        // Undetected without CRC
+        let mut storage = ResilientStorage::<u32>::new(&0);
+        storage.update(&3);
+        storage.storage.storage.storage_b.value.value.value ^= 0x100;
+        assert_eq!(storage.try_get_ref(), Ok(&0x103));
+    }
+
+    #[test]
+    fn resilient_reset_to_default() {
+        let mut storage = resilient_storage(RecoveryPolicy::ResetToDefault);
+        storage.storage.storage.storage_b.value.value.value ^= 0x100;
+        assert_eq!(storage.recover(), Some(StorageError::Corrupted));
+        assert_eq!(storage.try_get_ref(), Ok(&0));
+        assert_eq!(storage.recover(), None);
+    }
+
+    #[test]
+    fn resilient_keep_last_good() {
+        // Current copy corrupted: the previous one is restored
+        let mut storage = resilient_storage(RecoveryPolicy::KeepLastGood);
+        storage.storage.storage.storage_b.value.value.value ^= 0x100;
+        assert_eq!(storage.get(), &2);
+        storage.update(&4);
+        assert_eq!(storage.get(), &4);
+
+        // Both copies intact but marked as invalid: the newest one is restored
+        let mut storage = resilient_storage(RecoveryPolicy::KeepLastGood);
+        storage.storage.storage.storage_b.flag.value = 0;
+        assert_eq!(storage.try_get_ref(), Err(StorageError::Invalid));
+        assert_eq!(storage.recover(), Some(StorageError::Invalid));
+        assert_eq!(storage.try_get_ref(), Ok(&3));
+    }
+
+    /// Updates `storage` as if the write of the new copy had been interrupted before its flag is
+    /// restored.
+    fn torn_update(storage: &mut ResilientStorage<u32>, value: u32) {
+        let atomic = &storage.storage.storage;
+        let flags = [atomic.storage_a.flag.value, atomic.storage_b.flag.value];
+        let sequences = [
+            atomic.storage_a.value.value.sequence,
+            atomic.storage_b.value.value.sequence,
+        ];
+        storage.update(&value);
+        let atomic = &mut storage.storage.storage;
+        for (i, copy) in [&mut atomic.storage_a, &mut atomic.storage_b]
+            .into_iter()
+            .enumerate()
+        {
+            copy.flag.value = if copy.value.value.sequence != sequences[i] {
+                0
+            } else {
+                flags[i]
+            };
         }
     }
+
+    #[test]
+    fn resilient_torn_update() {
+        // A previous torn update left both copies valid, B being the newest
+        let mut storage = resilient_storage(RecoveryPolicy::KeepLastGood);
+        storage.storage.storage.storage_a.flag.value = STORAGE_VALID;
+        assert_eq!(storage.try_get_ref(), Ok(&3));
+
+        torn_update(&mut storage, 4);
+        assert_eq!(storage.try_get_ref(), Ok(&3));
+        storage.update(&5);
+        assert_eq!(storage.try_get_ref(), Ok(&5));
+        torn_update(&mut storage, 6);
+        assert_eq!(storage.try_get_ref(), Ok(&5));
+    }
+
+    /// The CRC of the initial value is computed at compile time.
+    static VERSIONED: VersionedStorage<Settings, 8> = VersionedStorage::new(&Settings {
+        blind_signing: true,
+        display_hash: true,
+        timeout: 30,
+    })
+    .set_crc(true);
+
+    #[test]
+    fn versioned_static() {
+        let data = VERSIONED.storage.try_get_ref().map_err(|_| ())?;
+        assert_eq!(data.version, 2);
+        assert_eq!(unsafe { data.payload.value.timeout }, 30);
+    }
+
+    #[test]
+    fn versioned_keep_last_good() {
+        let first = Settings {
+            blind_signing: true,
+            display_hash: false,
+            timeout: 30,
+        };
+        let second = Settings {
+            timeout: 60,
+            ..first
+        };
+        let mut storage = VersionedStorage::<Settings, 8>::new(&Settings::default())
+            .set_crc(true)
+            .set_recovery_policy(RecoveryPolicy::KeepLastGood);
+        storage.update(&first);
+        storage.update(&second);
+        assert_eq!(storage.get(), &second);
+
+        // The current copy is A after two updates
+        storage.storage.storage.storage_a.value.value.value.version ^= 0x100;
+        assert_eq!(storage.get(), &first);
+    }
+
+    #[test]
+    fn collection_order() {
+        let mut collection = Collection::<u32, 4>::new(0);
+        for value in [10, 20, 30] {
+            collection.add(&value).map_err(|_| ())?;
+        }
+        collection.remove(0);
+        // The freed slot is reused, but the new item is the last one
+        collection.add(&40).map_err(|_| ())?;
+        assert_eq!(collection.len(), 3);
+        assert_eq!(collection.get(0), Some(&20));
+        assert_eq!(collection.get(2), Some(&40));
+        let mut items = collection.into_iter();
+        assert_eq!(items.next(), Some(&20));
+        assert_eq!(items.next(), Some(&30));
+        assert_eq!(items.next(), Some(&40));
+        assert_eq!(items.next(), None);
+    }
+
+    #[test]
+    fn collection_replace() {
+        let mut collection = Collection::<u32, 3>::new(0);
+        for value in [10, 20] {
+            collection.add(&value).map_err(|_| ())?;
+        }
+        assert_eq!(collection.replace(0, &11).is_ok(), true);
+        assert_eq!(collection.get(0), Some(&11));
+        assert_eq!(collection.get(1), Some(&20));
+        assert_eq!(collection.len(), 2);
+
+        // The new value is written in a free slot
+        collection.add(&30).map_err(|_| ())?;
+        assert_eq!(collection.replace(1, &21).is_err(), true);
+        assert_eq!(collection.get(1), Some(&20));
+    }
+
+    #[test]
+    fn collection_remove() {
+        let mut collection = Collection::<u32, 4>::new(0);
+        for value in [10, 20, 30, 40] {
+            collection.add(&value).map_err(|_| ())?;
+        }
+        assert_eq!(collection.add(&50).is_err(), true);
+
+        collection.remove(1);
+        assert_eq!(collection.len(), 3);
+        assert_eq!(collection.get(1), Some(&30));
+        assert_eq!(collection.get(3), None);
+        collection.remove(2);
+        assert_eq!(collection.get(1), Some(&30));
+        assert_eq!(collection.remaining(), 2);
+
+        collection.clear();
+        assert_eq!(collection.is_empty(), true);
+        assert_eq!(collection.get(0), None);
+    }
+
+    #[test]
+    fn collection_stale_state() {
+        let mut collection = Collection::<u32, 4>::new(0);
+        collection.add(&10).map_err(|_| ())?;
+
+        // State written with another layout
+        collection.state.update(&CollectionState {
+            magic: 0xa5a5_a5a5,
+            ..CollectionState::EMPTY
+        });
+        assert_eq!(collection.is_empty(), true);
+        assert_eq!(collection.into_iter().next(), None);
+
+        // Inconsistent states
+        let mut state = CollectionState::<4>::EMPTY;
+        state.len = 9;
+        collection.state.update(&state);
+        assert_eq!(collection.len(), 0);
+        state.len = 1;
+        state.keys[0] = 7;
+        collection.state.update(&state);
+        assert_eq!(collection.get(0), None);
+
+        // Both copies invalid
+        collection.state.storage_a.invalidate();
+        collection.state.storage_b.invalidate();
+        assert_eq!(collection.remaining(), 4);
+
+        // The state is overwritten by the next change
+        collection.add(&20).map_err(|_| ())?;
+        assert_eq!(collection.len(), 1);
+        assert_eq!(collection.get(0), Some(&20));
+    }
+
+    #[test]
+    fn kv_store_full() {
+        let mut store = KvStore::<3>::new();
+        let value = [0xaa; 100];
+        let mut count = 0;
+        while store.put(&[b'k', count], &value).is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 8);
+
+        // Replacing a value requires room for both values, which is checked before compacting
+        let pages = store.pages;
+        assert_eq!(store.put(b"k\x00", &[0xbb; 100]).is_err(), true);
+        let unchanged = pages
+            .iter()
+            .zip(&store.pages)
+            .all(|(a, b)| a.data == b.data);
+        assert_eq!(unchanged, true);
+        assert_eq!(store.get(b"k\x00"), Some(&value[..]));
+
+        assert_eq!(store.delete(b"k\x07"), true);
+        assert_eq!(store.put(b"k\x00", &[0xbb; 100]).is_ok(), true);
+        assert_eq!(store.get(b"k\x00"), Some(&[0xbb; 100][..]));
+        assert_eq!(store.len(), 7);
+    }
+
+    #[test]
+    fn kv_store_delete() {
+        let mut store = KvStore::<2>::new();
+        store.put(b"alice", b"0x01").map_err(|_| ())?;
+        store.put(b"bob", b"0x02").map_err(|_| ())?;
+
+        assert_eq!(store.delete(b"alice"), true);
+        assert_eq!(store.delete(b"alice"), false);
+        assert_eq!(store.get(b"alice"), None);
+        assert_eq!(store.contains_key(b"bob"), true);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn kv_store_compaction() {
+        let mut store = KvStore::<2>::new();
+        store.put(b"alice", b"0x01").map_err(|_| ())?;
+        for i in 0..50u8 {
+            assert_eq!(store.put(b"bob", &[i; 100]).is_ok(), true);
+        }
+        assert_eq!(store.get(b"bob"), Some(&[49; 100][..]));
+
+        assert_eq!(store.compact().is_ok(), true);
+        let mut entries = store.iter();
+        assert_eq!(entries.next(), Some((&b"alice"[..], &b"0x01"[..])));
+        assert_eq!(entries.next(), Some((&b"bob"[..], &[49; 100][..])));
+        assert_eq!(entries.next(), None);
+    }
+
+    #[test]
+    fn kv_store_clear() {
+        let mut store = KvStore::<2>::new();
+        store.put(b"alice", b"0x01").map_err(|_| ())?;
+        store.put(b"bob", b"0x02").map_err(|_| ())?;
+
+        store.clear();
+        assert_eq!(store.is_empty(), true);
+        assert_eq!(store.get(b"bob"), None);
+        assert_eq!(store.put(b"bob", b"0x03").is_ok(), true);
+        assert_eq!(store.get(b"bob"), Some(&b"0x03"[..]));
+    }
+
+    #[test]
+    fn kv_store_torn_put() {
+        let mut store = KvStore::<2>::new();
+        store.put(b"a", b"1").map_err(|_| ())?;
+
+        // Interrupted put, whose value holds a record of "a" after 6 bytes
+        let mut buffer = [0u8; KV_PAGE_SIZE];
+        let value = [0xff, STORAGE_VALID, 1, 0, 1, b'a', b'x'];
+        let record = kv_encode(&mut buffer, b"b", &value);
+        let page = store.head().ok_or(())?;
+        let offset = store
+            .page_records(page)
+            .last()
+            .map(|r| r.offset + r.len())
+            .ok_or(())?;
+        store.write(page, offset + 1, &record[1..]);
+        assert_eq!(store.len(), 1);
+
+        // A shorter record of 6 bytes is written at the same offset
+        store.put(b"c", b"2").map_err(|_| ())?;
+        assert_eq!(store.get(b"a"), Some(&b"1"[..]));
+        assert_eq!(store.len(), 2);
+    }
 }
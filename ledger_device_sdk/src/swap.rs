@@ -0,0 +1,368 @@
+//! Support of the library calls made by the Exchange application for swaps.
+//!
+//! When a swap is performed, the Exchange application calls the coin application as a library
+//! (`os_lib_call`) to check an address, format amounts, and finally sign the transaction. These
+//! calls do not go through `sample_main`, but through `library_main`, which hands them over to
+//! [`lib_main`] when defined with [`libcall_main!`](crate::libcall_main):
+//!
+//! ```
+//! libcall_main!(swap: MyApp);
+//! ```
+//!
+//! The RAM of the application overlaps the one of the Exchange application, in which the
+//! parameters of the call are stored, and is not initialized when the application is called.
+//! Exchange resumes after CHECK_ADDRESS and GET_PRINTABLE_AMOUNT, so their handlers run without
+//! initializing it: they must not access global variables (other than constants) nor allocate.
+//! For SIGN_TRANSACTION, after which Exchange does not resume, [`lib_main`] initializes the RAM
+//! and the IO stack once the parameters have been copied, and the handler can run the
+//! application as usual.
+use crate::libcall::LibArgs;
+use core::ffi::{c_char, c_int};
+use ledger_secure_sdk_sys::{lib_init_io, lib_init_ram};
+
+/// Identifier of the Exchange application, as a caller.
+const EXCHANGE_ID: u32 = 0x100;
+
+const SIGN_TRANSACTION: u32 = 2;
+const CHECK_ADDRESS: u32 = 3;
+const GET_PRINTABLE_AMOUNT: u32 = 4;
+
+/// Size of the buffer holding a printable amount, including the terminating NUL byte.
+pub const MAX_PRINTABLE_AMOUNT_SIZE: usize = 50;
+
+/// Maximum length of a coin configuration.
+pub const COIN_CONFIG_MAX_LEN: usize = 64;
+/// Maximum length of serialized address parameters (usually a derivation path).
+pub const ADDRESS_PARAMETERS_MAX_LEN: usize = 64;
+/// Maximum length of an address.
+pub const ADDRESS_MAX_LEN: usize = 128;
+/// Maximum length of an address extra identifier (memo, destination tag...).
+pub const EXTRA_ID_MAX_LEN: usize = 64;
+/// Maximum length of an amount.
+pub const AMOUNT_MAX_LEN: usize = 32;
+
+// Parameters of the library calls, laid out as in `swap_lib_calls.h`.
+
+// `result` is only written, for the caller
+#[repr(C)]
+#[allow(dead_code)]
+struct CheckAddressParameters {
+    coin_configuration: *const u8,
+    coin_configuration_length: u8,
+    address_parameters: *const u8,
+    address_parameters_length: u8,
+    address_to_check: *const c_char,
+    extra_id_to_check: *const c_char,
+    result: c_int,
+}
+
+#[repr(C)]
+struct GetPrintableAmountParameters {
+    coin_configuration: *const u8,
+    coin_configuration_length: u8,
+    amount: *const u8,
+    amount_length: u8,
+    is_fee: bool,
+    printable_amount: [c_char; MAX_PRINTABLE_AMOUNT_SIZE],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct CreateTransactionParameters {
+    coin_configuration: *const u8,
+    coin_configuration_length: u8,
+    amount: *const u8,
+    amount_length: u8,
+    fee_amount: *const u8,
+    fee_amount_length: u8,
+    destination_address: *const c_char,
+    destination_address_extra_id: *const c_char,
+    result: u8,
+}
+
+/// Bytes copied from the memory of the Exchange application.
+#[derive(Clone, Copy)]
+struct Field<const N: usize> {
+    data: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Field<N> {
+    /// Copies `len` bytes from `ptr`, or returns `None` if they do not fit.
+    unsafe fn from_raw(ptr: *const u8, len: u8) -> Option<Self> {
+        let len = len as usize;
+        let mut data = [0u8; N];
+        if len > N {
+            return None;
+        }
+        if len > 0 {
+            core::ptr::copy_nonoverlapping(ptr, data.as_mut_ptr(), len);
+        }
+        Some(Field { data, len })
+    }
+
+    /// Copies the NUL-terminated string at `ptr` (which may be null), or returns `None` if it
+    /// does not fit.
+    unsafe fn from_c_str(ptr: *const c_char) -> Option<Self> {
+        let mut data = [0u8; N];
+        if ptr.is_null() {
+            return Some(Field { data, len: 0 });
+        }
+        let ptr = ptr as *const u8;
+        for (i, c) in data.iter_mut().enumerate() {
+            *c = *ptr.add(i);
+            if *c == 0 {
+                return Some(Field { data, len: i });
+            }
+        }
+        None
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// Parameters of the CHECK_ADDRESS call.
+pub struct CheckAddressParams {
+    coin_config: Field<COIN_CONFIG_MAX_LEN>,
+    address_parameters: Field<ADDRESS_PARAMETERS_MAX_LEN>,
+    address: Field<ADDRESS_MAX_LEN>,
+    extra_id: Field<EXTRA_ID_MAX_LEN>,
+}
+
+impl CheckAddressParams {
+    /// Configuration of the coin, for applications supporting several coins.
+    pub fn coin_config(&self) -> &[u8] {
+        self.coin_config.as_slice()
+    }
+
+    /// Serialized parameters from which the address is derived, usually a derivation path.
+    pub fn address_parameters(&self) -> &[u8] {
+        self.address_parameters.as_slice()
+    }
+
+    /// Address to check.
+    pub fn address(&self) -> &[u8] {
+        self.address.as_slice()
+    }
+
+    /// Extra identifier to check, empty if none.
+    pub fn extra_id(&self) -> &[u8] {
+        self.extra_id.as_slice()
+    }
+
+    unsafe fn read(params: *const CheckAddressParameters) -> Option<Self> {
+        let p = &*params;
+        Some(CheckAddressParams {
+            coin_config: Field::from_raw(p.coin_configuration, p.coin_configuration_length)?,
+            address_parameters: Field::from_raw(p.address_parameters, p.address_parameters_length)?,
+            address: Field::from_c_str(p.address_to_check)?,
+            extra_id: Field::from_c_str(p.extra_id_to_check)?,
+        })
+    }
+}
+
+/// Parameters of the GET_PRINTABLE_AMOUNT call.
+pub struct PrintableAmountParams {
+    coin_config: Field<COIN_CONFIG_MAX_LEN>,
+    amount: Field<AMOUNT_MAX_LEN>,
+    is_fee: bool,
+}
+
+impl PrintableAmountParams {
+    /// Configuration of the coin, for applications supporting several coins.
+    pub fn coin_config(&self) -> &[u8] {
+        self.coin_config.as_slice()
+    }
+
+    /// Amount to format, as a big-endian integer.
+    pub fn amount(&self) -> &[u8] {
+        self.amount.as_slice()
+    }
+
+    /// Whether the amount is a fee.
+    pub fn is_fee(&self) -> bool {
+        self.is_fee
+    }
+
+    unsafe fn read(params: *const GetPrintableAmountParameters) -> Option<Self> {
+        let p = &*params;
+        Some(PrintableAmountParams {
+            coin_config: Field::from_raw(p.coin_configuration, p.coin_configuration_length)?,
+            amount: Field::from_raw(p.amount, p.amount_length)?,
+            is_fee: p.is_fee,
+        })
+    }
+}
+
+/// Parameters of the SIGN_TRANSACTION call.
+pub struct SignTxParams {
+    coin_config: Field<COIN_CONFIG_MAX_LEN>,
+    amount: Field<AMOUNT_MAX_LEN>,
+    fee: Field<AMOUNT_MAX_LEN>,
+    destination: Field<ADDRESS_MAX_LEN>,
+    extra_id: Field<EXTRA_ID_MAX_LEN>,
+}
+
+impl SignTxParams {
+    /// Configuration of the coin, for applications supporting several coins.
+    pub fn coin_config(&self) -> &[u8] {
+        self.coin_config.as_slice()
+    }
+
+    /// Amount of the transaction, as a big-endian integer.
+    pub fn amount(&self) -> &[u8] {
+        self.amount.as_slice()
+    }
+
+    /// Fee of the transaction, as a big-endian integer.
+    pub fn fee(&self) -> &[u8] {
+        self.fee.as_slice()
+    }
+
+    /// Destination address of the transaction.
+    pub fn destination(&self) -> &[u8] {
+        self.destination.as_slice()
+    }
+
+    /// Extra identifier of the destination, empty if none.
+    pub fn extra_id(&self) -> &[u8] {
+        self.extra_id.as_slice()
+    }
+
+    unsafe fn read(params: *const CreateTransactionParameters) -> Option<Self> {
+        let p = &*params;
+        Some(SignTxParams {
+            coin_config: Field::from_raw(p.coin_configuration, p.coin_configuration_length)?,
+            amount: Field::from_raw(p.amount, p.amount_length)?,
+            fee: Field::from_raw(p.fee_amount, p.fee_amount_length)?,
+            destination: Field::from_c_str(p.destination_address)?,
+            extra_id: Field::from_c_str(p.destination_address_extra_id)?,
+        })
+    }
+}
+
+/// Handlers of the library calls made by the Exchange application.
+pub trait SwapApp {
+    /// Returns true if [`CheckAddressParams::address`] (and its extra identifier, if any) is the
+    /// address derived by the application from [`CheckAddressParams::address_parameters`].
+    ///
+    /// The RAM of the application is not initialized: global variables and the heap must not be
+    /// used.
+    fn check_address(params: &CheckAddressParams) -> bool;
+
+    /// Writes the amount (or fee) to display in `out`, and returns its length, or `None` if it
+    /// is invalid. `out` is [`MAX_PRINTABLE_AMOUNT_SIZE`] - 1 bytes long.
+    ///
+    /// The RAM of the application is not initialized: global variables and the heap must not be
+    /// used.
+    fn get_printable_amount(params: &PrintableAmountParams, out: &mut [u8]) -> Option<usize>;
+
+    /// Runs the application to sign a transaction, which must be rejected unless its amount, fee
+    /// and destination match `params`. Returns true if the transaction has been signed.
+    ///
+    /// The RAM and the IO stack have been initialized, so APDUs can be exchanged and screens
+    /// displayed as when the application is started normally.
+    fn sign_transaction(params: &SignTxParams) -> bool;
+}
+
+/// Handles the library call whose parameters are pointed to by `arg0`.
+///
+/// Parameters are copied to the stack (and for SIGN_TRANSACTION, the RAM of the application is
+/// initialized), then the call is dispatched to `A`, and its result is written back to the
+/// memory of the Exchange application.
+/// Parameters which do not fit in the buffers of this module make the call fail. Calls from
+/// another application than Exchange, and unknown calls, are ignored.
+pub fn lib_main<A: SwapApp>(arg0: u32) {
+    let args = arg0 as *const LibArgs;
+    let (id, command, parameters) = unsafe { ((*args).id, (*args).command, (*args).parameters) };
+    if id != EXCHANGE_ID {
+        return;
+    }
+
+    match command {
+        CHECK_ADDRESS => {
+            let raw = parameters as *mut CheckAddressParameters;
+            let params = unsafe { CheckAddressParams::read(raw) };
+            let ok = params.is_some_and(|params| A::check_address(&params));
+            unsafe { (*raw).result = ok as c_int };
+        }
+        GET_PRINTABLE_AMOUNT => {
+            let raw = parameters as *mut GetPrintableAmountParameters;
+            let params = unsafe { PrintableAmountParams::read(raw) };
+            let mut out = [0u8; MAX_PRINTABLE_AMOUNT_SIZE];
+            let len = params
+                .and_then(|params| {
+                    A::get_printable_amount(&params, &mut out[..MAX_PRINTABLE_AMOUNT_SIZE - 1])
+                })
+                .unwrap_or(0)
+                .min(MAX_PRINTABLE_AMOUNT_SIZE - 1);
+            out[len..].fill(0);
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    out.as_ptr(),
+                    (*raw).printable_amount.as_mut_ptr() as *mut u8,
+                    MAX_PRINTABLE_AMOUNT_SIZE,
+                )
+            };
+        }
+        SIGN_TRANSACTION => {
+            let raw = parameters as *mut CreateTransactionParameters;
+            let params = unsafe { SignTxParams::read(raw) };
+            lib_init_ram();
+            let ok = params.is_some_and(|params| {
+                lib_init_io();
+                A::sign_transaction(&params)
+            });
+            unsafe { (*raw).result = ok as u8 };
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    #[test]
+    fn swap_params() {
+        let path = [0x03, 0x80, 0x00, 0x00, 0x2c];
+        let raw = CheckAddressParameters {
+            coin_configuration: core::ptr::null(),
+            coin_configuration_length: 0,
+            address_parameters: path.as_ptr(),
+            address_parameters_length: path.len() as u8,
+            address_to_check: b"0xde0b2956\0".as_ptr() as *const c_char,
+            extra_id_to_check: core::ptr::null(),
+            result: 0,
+        };
+        let params = unsafe { CheckAddressParams::read(&raw) };
+        assert_eq!(params.is_some(), true);
+        let params = params.ok_or(())?;
+        assert_eq!(params.coin_config(), &[]);
+        assert_eq!(params.address_parameters(), &path);
+        assert_eq!(params.address(), b"0xde0b2956");
+        assert_eq!(params.extra_id(), &[]);
+
+        let amount = [0x01, 0x00];
+        let fee = [0x10];
+        let long_address = [b'a'; ADDRESS_MAX_LEN + 1];
+        let raw = CreateTransactionParameters {
+            coin_configuration: core::ptr::null(),
+            coin_configuration_length: 0,
+            amount: amount.as_ptr(),
+            amount_length: 2,
+            fee_amount: fee.as_ptr(),
+            fee_amount_length: 1,
+            destination_address: long_address.as_ptr() as *const c_char,
+            destination_address_extra_id: core::ptr::null(),
+            result: 0,
+        };
+        let params = unsafe { SignTxParams::read(&raw) };
+        assert_eq!(params.is_none(), true);
+    }
+}
@@ -1,8 +1,11 @@
+#[cfg(not(feature = "mock"))]
 use core::arch::asm;
+#[cfg(not(feature = "mock"))]
 use core::panic::PanicInfo;
 
 /// Debug 'print' function that uses ARM semihosting
 /// Prints only strings with no formatting
+#[cfg(not(feature = "mock"))]
 pub fn debug_print(s: &str) {
     let p = s.as_bytes().as_ptr();
     for i in 0..s.len() {
@@ -17,6 +20,12 @@ pub fn debug_print(s: &str) {
     }
 }
 
+/// Debug 'print' function that prints to the standard output of the host
+#[cfg(feature = "mock")]
+pub fn debug_print(s: &str) {
+    std::print!("{}", s);
+}
+
 pub fn to_hex(m: u32) -> [u8; 8] {
     let mut hex = [0u8; 8];
     let mut i = 0;
@@ -30,6 +39,7 @@ pub fn to_hex(m: u32) -> [u8; 8] {
     hex
 }
 
+#[cfg(not(feature = "mock"))]
 #[cfg_attr(test, panic_handler)]
 pub fn test_panic(info: &PanicInfo) -> ! {
     debug_print("Panic! ");
@@ -51,7 +61,7 @@ pub struct TestType {
 
 /// Custom test runner that uses non-formatting print functions
 /// using semihosting. Only reports 'Ok' or 'fail'.
-#[cfg(feature = "speculos")]
+#[cfg(all(feature = "speculos", not(feature = "mock")))]
 pub fn sdk_test_runner(tests: &[&TestType]) {
     use core::ffi::c_void;
     use ledger_secure_sdk_sys::{pic, pic_rs};
@@ -94,6 +104,30 @@ pub fn sdk_test_runner(tests: &[&TestType]) {
     ledger_secure_sdk_sys::exit_app(0);
 }
 
+/// Custom test runner used when building for the host with the `mock`
+/// feature. Only reports 'Ok' or 'fail'.
+#[cfg(all(feature = "speculos", feature = "mock"))]
+pub fn sdk_test_runner(tests: &[&TestType]) {
+    let mut failures = 0;
+    debug_print("--- Tests ---\n");
+    for test in tests {
+        match (test.f)() {
+            Ok(()) => debug_print("\x1b[1;32m   ok   \x1b[0m"),
+            Err(()) => {
+                failures += 1;
+                debug_print("\x1b[1;31m  fail  \x1b[0m")
+            }
+        }
+        debug_print(test.modname);
+        debug_print("::");
+        debug_print(test.name);
+        debug_print("\n");
+    }
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
 /// This variant of `assert_eq!()` returns an error
 /// `Err(())` instead of panicking, to prevent tests
 /// from exiting on first failure
@@ -0,0 +1,208 @@
+//! Authenticated tokens, used to keep state on the host between APDUs.
+//!
+//! Applications which need data of a previous command (e.g. trusted inputs) can return it to
+//! the host as a token sealed with a [`TokenKey`], and authenticate it when it is sent back,
+//! instead of storing it on the device.
+use crate::ecc::{bip32_derive, CurvesId, CxError, Secret};
+use crate::hmac::{sha2::Sha2_256, HMACError, HMACInit};
+use crate::io::{Reply, StatusWords, SyscallError};
+use crate::random::rand_bytes;
+use zeroize::Zeroize;
+
+/// Length of the expiry prefixing the data of a token.
+const EXPIRY_LEN: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TokenError {
+    /// The token was not sealed with this key and domain, or has been modified.
+    InvalidTag,
+    /// The token has expired.
+    Expired,
+    /// The token is too short, or does not fit in the output buffer.
+    InvalidLength,
+    /// Error of the underlying HMAC computation.
+    Hmac(HMACError),
+}
+
+impl From<HMACError> for TokenError {
+    fn from(e: HMACError) -> TokenError {
+        TokenError::Hmac(e)
+    }
+}
+
+impl From<TokenError> for Reply {
+    fn from(e: TokenError) -> Reply {
+        match e {
+            TokenError::InvalidTag => SyscallError::Security.into(),
+            TokenError::Expired => SyscallError::Timeout.into(),
+            TokenError::InvalidLength => StatusWords::BadLen.into(),
+            TokenError::Hmac(e) => e.into(),
+        }
+    }
+}
+
+/// Key sealing and opening tokens.
+///
+/// A token is made of a big-endian 32-bit expiry, the data, and a 16-byte tag, which is the first
+/// 16 bytes of the HMAC-SHA256 of `domain length (1 byte) || domain || expiry || data`. Tokens
+/// are authenticated, not encrypted: the data is visible to the host.
+///
+/// The domain separates tokens used for different purposes, so that a token sealed for one
+/// purpose cannot be used for another. The expiry is compared to a value given by the
+/// application when opening the token, such as a number of ticker events since the application
+/// started, or a counter of transactions.
+///
+/// # Examples
+///
+/// ```
+/// let key = TokenKey::from_path(&TOKEN_PATH)?;
+///
+/// // GET_TRUSTED_INPUT
+/// let len = key.seal(b"trusted input", u32::MAX, &input, &mut buffer)?;
+/// comm.append(&buffer[..len]);
+///
+/// // SIGN_TX
+/// let input = key.open(b"trusted input", 0, comm.get_data()?)?;
+/// ```
+pub struct TokenKey {
+    key: [u8; 32],
+}
+
+/// Cleanup the key from memory when dropping this structure.
+impl Drop for TokenKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl TokenKey {
+    /// Length of the tag ending a token.
+    pub const TAG_LEN: usize = 16;
+    /// Number of bytes added to the data of a token.
+    pub const OVERHEAD: usize = EXPIRY_LEN + Self::TAG_LEN;
+
+    /// Creates a random key, which is lost when the application exits: its tokens are only
+    /// valid until then.
+    pub fn random() -> Self {
+        let mut key = [0u8; 32];
+        rand_bytes(&mut key);
+        TokenKey { key }
+    }
+
+    /// Derives a key from the seed of the device, using the derivation path `path`: its tokens
+    /// remain valid across restarts of the application, for the same seed.
+    ///
+    /// `path` should be dedicated to this key, and not be used to derive signing keys.
+    pub fn from_path(path: &[u32]) -> Result<Self, CxError> {
+        let mut node = Secret::<64>::new();
+        bip32_derive(CurvesId::Secp256k1, path, node.as_mut(), None)?;
+        let mut key = [0u8; 32];
+        Sha2_256::new(&node.as_ref()[..32])
+            .hmac(b"token key", &mut key)
+            .map_err(|_| CxError::InternalError)?;
+        Ok(TokenKey { key })
+    }
+
+    /// Seals `data` in a token for `domain`, expiring after `expiry`, and writes it to `out`.
+    /// Returns the length of the token, which is `data.len()` + [`TokenKey::OVERHEAD`].
+    pub fn seal(
+        &self,
+        domain: &[u8],
+        expiry: u32,
+        data: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, TokenError> {
+        let len = data.len() + Self::OVERHEAD;
+        if out.len() < len {
+            return Err(TokenError::InvalidLength);
+        }
+        out[..EXPIRY_LEN].copy_from_slice(&expiry.to_be_bytes());
+        out[EXPIRY_LEN..EXPIRY_LEN + data.len()].copy_from_slice(data);
+        let (body, tag) = out[..len].split_at_mut(len - Self::TAG_LEN);
+        tag.copy_from_slice(&self.tag(domain, body)?);
+        Ok(len)
+    }
+
+    /// Opens `token` for `domain`, and returns its data if its tag is valid and it has not
+    /// expired at `now`.
+    pub fn open<'a>(
+        &self,
+        domain: &[u8],
+        now: u32,
+        token: &'a [u8],
+    ) -> Result<&'a [u8], TokenError> {
+        if token.len() < Self::OVERHEAD {
+            return Err(TokenError::InvalidLength);
+        }
+        let (body, tag) = token.split_at(token.len() - Self::TAG_LEN);
+        let expected = self.tag(domain, body)?;
+        // Constant-time comparison of the tags
+        let diff = expected
+            .iter()
+            .zip(tag.iter())
+            .fold(0, |d, (a, b)| d | (a ^ b));
+        if diff != 0 {
+            return Err(TokenError::InvalidTag);
+        }
+        let mut expiry = [0u8; EXPIRY_LEN];
+        expiry.copy_from_slice(&body[..EXPIRY_LEN]);
+        if now > u32::from_be_bytes(expiry) {
+            return Err(TokenError::Expired);
+        }
+        Ok(&body[EXPIRY_LEN..])
+    }
+
+    /// Returns the tag of `body` (expiry and data) for `domain`.
+    fn tag(&self, domain: &[u8], body: &[u8]) -> Result<[u8; Self::TAG_LEN], TokenError> {
+        let domain_len = u8::try_from(domain.len()).map_err(|_| TokenError::InvalidLength)?;
+        let mut digest = [0u8; 32];
+        let mut mac = Sha2_256::new(&self.key);
+        mac.update(&[domain_len])?;
+        mac.update(domain)?;
+        mac.update(body)?;
+        mac.finalize(&mut digest)?;
+        let mut tag = [0u8; Self::TAG_LEN];
+        tag.copy_from_slice(&digest[..Self::TAG_LEN]);
+        Ok(tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_eq_err as assert_eq;
+    use crate::testing::TestType;
+    use testmacro::test_item as test;
+
+    const PATH: [u32; 2] = [0x8000_0000 | 0x544f4b, 0x8000_0000];
+
+    #[test]
+    fn token_seal_open() {
+        let key = TokenKey::from_path(&PATH).map_err(|_| ())?;
+        let mut token = [0u8; 32];
+
+        let len = key.seal(b"trusted input", 10, b"state", &mut token);
+        assert_eq!(len, Ok(5 + TokenKey::OVERHEAD));
+        let token = &mut token[..5 + TokenKey::OVERHEAD];
+        assert_eq!(key.open(b"trusted input", 10, token), Ok(&b"state"[..]));
+
+        assert_eq!(
+            key.open(b"trusted input", 11, token),
+            Err(TokenError::Expired)
+        );
+        assert_eq!(key.open(b"other", 10, token), Err(TokenError::InvalidTag));
+        assert_eq!(
+            TokenKey::random().open(b"trusted input", 10, token),
+            Err(TokenError::InvalidTag)
+        );
+        token[4] ^= 0x01;
+        assert_eq!(
+            key.open(b"trusted input", 10, token),
+            Err(TokenError::InvalidTag)
+        );
+        assert_eq!(
+            key.open(b"trusted input", 10, &token[..4]),
+            Err(TokenError::InvalidLength)
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     buttons::ButtonEvent::*,
-    io::{self, ApduHeader, Comm, Event, Reply},
+    executor::Executor,
+    io::{self, ApduHeader, Comm, Event, Reply, Transport},
     uxapp::{UxEvent, BOLOS_UX_OK},
 };
 use ledger_secure_sdk_sys::{
@@ -133,57 +134,80 @@ impl<'a> Validator<'a> {
     }
 
     pub fn ask(&self) -> bool {
-        clear_screen();
         let mut buttons = ButtonsState::new();
+        let mut response = false;
+        self.redraw(response);
 
-        let mut lines = [Label::from_const("Cancel"), Label::from(self.message)];
+        loop {
+            if let Some(event) = get_event(&mut buttons) {
+                if let Some(answer) = self.on_button(event, &mut response) {
+                    return answer;
+                }
+            }
+        }
+    }
 
-        lines[0].bold = true;
+    /// Same as [`Validator::ask`], as a future run by `exec`, which keeps processing APDUs
+    /// while waiting for the user.
+    pub async fn ask_async<T, Tr: Transport, const N: usize>(
+        &self,
+        exec: &Executor<'_, T, Tr, N>,
+    ) -> bool
+    where
+        T: TryFrom<ApduHeader>,
+        Reply: From<<T as TryFrom<ApduHeader>>::Error>,
+    {
+        let mut response = false;
+        self.redraw(response);
 
-        let redraw = |lines_list: &[Label; 2]| {
-            clear_screen();
-            lines_list.place(Location::Middle, Layout::Centered, false);
+        loop {
+            let event = exec.next_button().await;
+            if let Some(answer) = self.on_button(event, &mut response) {
+                return answer;
+            }
+        }
+    }
 
-            UP_ARROW.display();
-            DOWN_ARROW.display();
+    fn redraw(&self, response: bool) {
+        let mut lines = [Label::from_const("Cancel"), Label::from(self.message)];
+        lines[0].bold = !response;
+        lines[1].bold = response;
 
-            crate::ui::screen_util::screen_update();
-        };
-        redraw(&lines);
+        clear_screen();
+        lines.place(Location::Middle, Layout::Centered, false);
 
-        let mut response = false;
+        UP_ARROW.display();
+        DOWN_ARROW.display();
 
-        loop {
-            match get_event(&mut buttons) {
-                Some(ButtonEvent::LeftButtonPress) => {
-                    UP_S_ARROW.instant_display();
-                }
-                Some(ButtonEvent::RightButtonPress) => {
-                    DOWN_S_ARROW.instant_display();
-                }
-                Some(ButtonEvent::LeftButtonRelease) => {
-                    UP_S_ARROW.erase();
-                    response = false;
-                    lines[0].bold = true;
-                    lines[1].bold = false;
-                    lines.place(Location::Middle, Layout::Centered, false);
-                    redraw(&lines);
-                }
-                Some(ButtonEvent::RightButtonRelease) => {
-                    DOWN_S_ARROW.erase();
-                    response = true;
-                    lines[0].bold = false;
-                    lines[1].bold = true;
-                    redraw(&lines);
-                }
-                Some(ButtonEvent::BothButtonsPress) => {
-                    UP_ARROW.erase();
-                    DOWN_ARROW.erase();
-                }
-                Some(ButtonEvent::BothButtonsRelease) => return response,
-                _ => (),
+        crate::ui::screen_util::screen_update();
+    }
+
+    /// Updates the screen after `event`, and returns the answer once both buttons are released.
+    fn on_button(&self, event: ButtonEvent, response: &mut bool) -> Option<bool> {
+        match event {
+            ButtonEvent::LeftButtonPress => {
+                UP_S_ARROW.instant_display();
             }
+            ButtonEvent::RightButtonPress => {
+                DOWN_S_ARROW.instant_display();
+            }
+            ButtonEvent::LeftButtonRelease => {
+                UP_S_ARROW.erase();
+                *response = false;
+                self.redraw(*response);
+            }
+            ButtonEvent::RightButtonRelease => {
+                DOWN_S_ARROW.erase();
+                *response = true;
+                self.redraw(*response);
+            }
+            ButtonEvent::BothButtonsPress => {
+                UP_ARROW.erase();
+                DOWN_ARROW.erase();
+            }
+            ButtonEvent::BothButtonsRelease => return Some(*response),
         }
+        None
     }
 }
 
@@ -605,6 +629,9 @@ impl<'a> MultiPageMenu<'a> {
                     }
                 },
                 io::Event::Command(ins) => return EventOrPageIndex::Event(io::Event::Command(ins)),
+                io::Event::Connection(event) => {
+                    return EventOrPageIndex::Event(io::Event::Connection(event))
+                }
                 io::Event::Ticker => {
                     if UxEvent::Event.request() != BOLOS_UX_OK {
                         // pin lock management
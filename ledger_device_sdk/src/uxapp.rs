@@ -15,6 +15,12 @@ fn os_ux_rs(params: &bolos_ux_params_t) {
     unsafe { os_ux(params as *const bolos_ux_params_t as *mut bolos_ux_params_t) };
 }
 
+/// Returns true if the device is locked, i.e. the PIN has not been validated since the device
+/// has been locked.
+pub fn is_device_locked() -> bool {
+    unsafe { os_global_pin_is_validated() != BOLOS_TRUE.try_into().unwrap() }
+}
+
 #[repr(u8)]
 pub enum UxEvent {
     Event = BOLOS_UX_EVENT,
@@ -619,6 +619,11 @@ impl SDKBuilder {
 }
 
 fn main() {
+    // The C SDK is neither built nor bound when mocking the device for host builds
+    if env::var_os("CARGO_FEATURE_MOCK").is_some() {
+        return;
+    }
+
     let mut sdk_builder = SDKBuilder::new();
     sdk_builder.gcc_toolchain();
     sdk_builder.device();
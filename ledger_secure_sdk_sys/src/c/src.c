@@ -14,7 +14,7 @@
   bolos_ux_asynch_callback_t G_io_asynch_ux_callback;
 #endif
 
-extern void sample_main();
+extern void sample_main(void);
 extern void heap_init();
 
 struct SectionSrc;
@@ -258,20 +258,8 @@ void link_pass_nvram(
 uint8_t G_io_apdu_buffer[260];
 #endif
 
-int c_main(void) {
-  __asm volatile("cpsie i");
-
-  // Update pointers for pic(), only issuing nvm_write() if we actually changed a pointer in the block.
-  // link_pass(&_rodata_len, &_rodata_src, &_rodata);
-  size_t rodata_len;
-  SYMBOL_ABSOLUTE_VALUE(rodata_len, _rodata_len);
-  struct SectionSrc* rodata_src;
-  SYMBOL_ABSOLUTE_VALUE(rodata_src, _rodata_src);
-  struct SectionDst* rodata;
-  SYMBOL_ABSOLUTE_VALUE(rodata, _rodata);
-
-  link_pass_nvram(rodata_len, rodata_src, rodata);
-
+// Relocates .data and clears .bss, which must be done before accessing global variables
+void c_init_ram(void) {
   size_t data_len;
   SYMBOL_ABSOLUTE_VALUE(data_len, _data_len);
   struct SectionSrc* sidata_src;
@@ -286,49 +274,113 @@ int c_main(void) {
   struct SectionDst* bss;
   SYMBOL_SBREL_ADDRESS(bss, _bss);
   memset(bss, 0, bss_len);
+}
+
+// Initializes the IO stack: seproxyhal, USB and BLE
+void c_boot_std(void) {
+  // below is a 'manual' implementation of `io_seproxyhal_init`
+#ifdef HAVE_MCU_PROTECT
+  unsigned char c[4];
+  c[0] = SEPROXYHAL_TAG_MCU;
+  c[1] = 0;
+  c[2] = 1;
+  c[3] = SEPROXYHAL_TAG_MCU_TYPE_PROTECT;
+  io_seproxyhal_spi_send(c, 4);
+#endif
+
+#ifdef HAVE_BLE
+  unsigned int plane = G_io_app.plane_mode;
+#endif
+
+  memset(&G_io_app, 0, sizeof(G_io_app));
+
+#ifdef HAVE_BLE
+  G_io_app.plane_mode = plane;
+#endif
+  G_io_app.apdu_state = APDU_IDLE;
+  G_io_app.apdu_length = 0;
+  G_io_app.apdu_media = IO_APDU_MEDIA_NONE;
+
+  G_io_app.ms = 0;
+  io_usb_hid_init();
+
+  USB_power(0);
+  USB_power(1);
+#ifdef HAVE_CCID
+  io_usb_ccid_set_card_inserted(1);
+#endif
+
+#ifdef HAVE_BLE
+  memset(&G_io_asynch_ux_callback, 0, sizeof(G_io_asynch_ux_callback));
+  BLE_power(1, NULL);
+#endif
+}
+
+// Calls a library application with os_lib_call, returning the exception raised by the call
+// (e.g. when the application is not installed), or 0 on success
+unsigned int c_lib_call(unsigned int *call_parameters) {
+  volatile unsigned int error = 0;
+  BEGIN_TRY {
+    TRY {
+      os_lib_call(call_parameters);
+    }
+    CATCH_OTHER(e) {
+      error = e;
+    }
+    FINALLY {
+    }
+  }
+  END_TRY;
+  return error;
+}
+
+// Entry point of library calls, overridden by applications which can be called as a library
+__attribute__((weak)) void library_main(int arg0) {
+  (void) arg0;
+}
+
+int c_main(int arg0) {
+  __asm volatile("cpsie i");
+
+  // Update pointers for pic(), only issuing nvm_write() if we actually changed a pointer in the block.
+  // link_pass(&_rodata_len, &_rodata_src, &_rodata);
+  size_t rodata_len;
+  SYMBOL_ABSOLUTE_VALUE(rodata_len, _rodata_len);
+  struct SectionSrc* rodata_src;
+  SYMBOL_ABSOLUTE_VALUE(rodata_src, _rodata_src);
+  struct SectionDst* rodata;
+  SYMBOL_ABSOLUTE_VALUE(rodata, _rodata);
+
+  link_pass_nvram(rodata_len, rodata_src, rodata);
 
   // formerly known as 'os_boot()'
   try_context_set(NULL);
 
+  if (arg0 != 0) {
+    // Called as a library through os_lib_call: the RAM of the application overlaps the one of
+    // the caller, so it is only initialized by the application (see `c_init_ram`), once the
+    // parameters of the call have been copied. Only the exception context, held on the stack,
+    // is set up here.
+    BEGIN_TRY {
+      TRY {
+        library_main(arg0);
+      }
+      CATCH_ALL {
+      }
+      FINALLY {
+        os_lib_end();
+      }
+    }
+    END_TRY;
+    return 0;
+  }
+
+  c_init_ram();
+
   for(;;) {
     BEGIN_TRY {
       TRY {
-        // below is a 'manual' implementation of `io_seproxyhal_init`
-    #ifdef HAVE_MCU_PROTECT
-        unsigned char c[4];
-        c[0] = SEPROXYHAL_TAG_MCU;
-        c[1] = 0;
-        c[2] = 1;
-        c[3] = SEPROXYHAL_TAG_MCU_TYPE_PROTECT;
-        io_seproxyhal_spi_send(c, 4);
-    #endif
-
-    #ifdef HAVE_BLE
-        unsigned int plane = G_io_app.plane_mode;
-    #endif
-
-        memset(&G_io_app, 0, sizeof(G_io_app));
-
-    #ifdef HAVE_BLE
-        G_io_app.plane_mode = plane;
-    #endif
-        G_io_app.apdu_state = APDU_IDLE;
-        G_io_app.apdu_length = 0;
-        G_io_app.apdu_media = IO_APDU_MEDIA_NONE;
-
-        G_io_app.ms = 0;
-        io_usb_hid_init();
-
-        USB_power(0);
-        USB_power(1);
-    #ifdef HAVE_CCID
-        io_usb_ccid_set_card_inserted(1);
-    #endif
-        
-    #ifdef HAVE_BLE
-        memset(&G_io_asynch_ux_callback, 0, sizeof(G_io_asynch_ux_callback));
-        BLE_power(1, NULL);
-    #endif
+        c_boot_std();
 
     #if !defined(HAVE_BOLOS) && defined(HAVE_PENDING_REVIEW_SCREEN)
         check_audited_app();
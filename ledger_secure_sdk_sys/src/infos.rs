@@ -39,6 +39,13 @@ static API_LEVEL: u8 = const_parse_api_level(env!("API_LEVEL"));
 #[cfg(not(target_os = "nanos"))]
 const_cstr!(ELF_API_LEVEL, "ledger.api_level", env!("API_LEVEL"));
 
+/// Name of the C SDK, also stored in the `ledger.sdk_name` ELF section
+pub const C_SDK_NAME: &str = env!("C_SDK_NAME");
+/// Commit hash of the C SDK, also stored in the `ledger.sdk_hash` ELF section
+pub const C_SDK_HASH: &str = env!("C_SDK_HASH");
+/// Version of the C SDK, also stored in the `ledger.sdk_version` ELF section
+pub const C_SDK_VERSION: &str = env!("C_SDK_VERSION");
+
 const_cstr!(ELF_TARGET, "ledger.target", env!("TARGET"));
 const_cstr!(ELF_TARGET_ID, "ledger.target_id", env!("TARGET_ID"));
 const_cstr!(ELF_TARGET_NAME, "ledger.target_name", env!("TARGET_NAME"));
@@ -52,10 +59,6 @@ const_cstr!(
     "ledger.rust_sdk_version",
     env!("CARGO_PKG_VERSION")
 );
-const_cstr!(ELF_C_SDK_NAME, "ledger.sdk_name", env!("C_SDK_NAME"));
-const_cstr!(ELF_C_SDK_HASH, "ledger.sdk_hash", env!("C_SDK_HASH"));
-const_cstr!(
-    ELF_C_SDK_VERSION,
-    "ledger.sdk_version",
-    env!("C_SDK_VERSION")
-);
+const_cstr!(ELF_C_SDK_NAME, "ledger.sdk_name", C_SDK_NAME);
+const_cstr!(ELF_C_SDK_HASH, "ledger.sdk_hash", C_SDK_HASH);
+const_cstr!(ELF_C_SDK_VERSION, "ledger.sdk_version", C_SDK_VERSION);
@@ -3,22 +3,85 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
+#[cfg(not(feature = "mock"))]
 use core::ffi::c_void;
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 use core::mem::MaybeUninit;
 
 pub mod buttons;
+#[cfg(not(feature = "mock"))]
 mod infos;
+#[cfg(not(feature = "mock"))]
+pub use infos::{C_SDK_HASH, C_SDK_NAME, C_SDK_VERSION};
+#[cfg(not(feature = "mock"))]
 pub mod seph;
 
 /// Wrapper for 'os_sched_exit'
 /// Exit application with status
+#[cfg(not(feature = "mock"))]
 pub fn exit_app(status: u8) -> ! {
     unsafe { os_sched_exit(status) }
 }
 
+#[cfg(not(feature = "mock"))]
+extern "C" {
+    fn c_init_ram();
+    fn c_boot_std();
+    fn c_lib_call(call_parameters: *mut u32) -> u32;
+}
+
+/// Initializes the RAM of the application (`.data`, `.bss` and heap).
+///
+/// This is done before `sample_main` when the application is started normally. When it is
+/// called as a library (through `library_main`), its RAM overlaps the one of the caller: this must only be called once
+/// the parameters of the call have been copied, and no global variable may be accessed before.
+#[cfg(not(feature = "mock"))]
+pub fn lib_init_ram() {
+    unsafe { c_init_ram() };
+    heap_init();
+}
+
+/// Initializes the IO stack (seproxyhal, USB and BLE).
+///
+/// This is done before `sample_main` when the application is started normally. When it is
+/// called as a library, this is only needed to exchange APDUs or display screens, after
+/// [`lib_init_ram`].
+#[cfg(not(feature = "mock"))]
+pub fn lib_init_io() {
+    unsafe { c_boot_std() }
+}
+
+/// Calls an application as a library with `os_lib_call`.
+///
+/// `call_parameters` holds the address of the name of the application, followed by the
+/// parameters given to it. Returns the exception raised by the call, if any (e.g. when the
+/// application is not installed).
+#[cfg(not(feature = "mock"))]
+pub fn lib_call(call_parameters: &mut [u32]) -> Result<(), u32> {
+    match unsafe { c_lib_call(call_parameters.as_mut_ptr()) } {
+        0 => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Writes `src_len` bytes from `src_adr` to `dst_adr`, in place of the `nvm_write` syscall:
+/// with the `mock` feature, the NVM is plain memory.
+///
+/// # Safety
+///
+/// `src_adr` must be valid for reads and `dst_adr` for writes of `src_len` bytes.
+#[cfg(feature = "mock")]
+pub unsafe fn nvm_write(
+    dst_adr: *mut core::ffi::c_void,
+    src_adr: *mut core::ffi::c_void,
+    src_len: u32,
+) {
+    core::ptr::copy(src_adr as *const u8, dst_adr as *mut u8, src_len as usize);
+}
+
 /// Performs code address translation for reading data located in the program
 /// and relocated during application installation.
+#[cfg(not(feature = "mock"))]
 pub fn pic_rs<T>(x: &T) -> &T {
     let ptr = unsafe { pic(x as *const T as *mut c_void) as *const T };
     unsafe { &*ptr }
@@ -30,27 +93,28 @@ pub fn pic_rs<T>(x: &T) -> &T {
 /// Warning: this is for corner cases as it is not directly possible to write
 /// data stored in the code as it resides in Flash memory. This is needed in
 /// particular when using the `nvm` module.
+#[cfg(not(feature = "mock"))]
 pub fn pic_rs_mut<T>(x: &mut T) -> &mut T {
     let ptr = unsafe { pic(x as *mut T as *mut c_void) as *mut T };
     unsafe { &mut *ptr }
 }
 
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 use critical_section::RawRestoreState;
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 use embedded_alloc::Heap;
 
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
 
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 struct CriticalSection;
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 critical_section::set_impl!(CriticalSection);
 
 /// Default empty implementation as we don't have concurrency.
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 unsafe impl critical_section::Impl for CriticalSection {
     unsafe fn acquire() -> RawRestoreState {}
     unsafe fn release(_restore_state: RawRestoreState) {}
@@ -61,7 +125,7 @@ unsafe impl critical_section::Impl for CriticalSection {
 /// The heap is stored in the stack, and has a fixed size.
 /// This method is called just before [sample_main].
 #[no_mangle]
-#[cfg(all(feature = "heap", not(target_os = "nanos")))]
+#[cfg(all(feature = "heap", not(target_os = "nanos"), not(feature = "mock")))]
 extern "C" fn heap_init() {
     // HEAP_SIZE comes from heap_size.rs, which is defined via env var and build.rs
     static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
@@ -69,8 +133,10 @@ extern "C" fn heap_init() {
 }
 
 #[no_mangle]
-#[cfg(any(not(feature = "heap"), target_os = "nanos"))]
+#[cfg(all(any(not(feature = "heap"), target_os = "nanos"), not(feature = "mock")))]
 extern "C" fn heap_init() {}
 
+#[cfg(not(feature = "mock"))]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+#[cfg(not(feature = "mock"))]
 include!(concat!(env!("OUT_DIR"), "/heap_size.rs"));